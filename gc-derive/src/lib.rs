@@ -0,0 +1,259 @@
+//! `#[derive(Trace)]` and `#[derive(Finalize)]` for types managed by
+//! `embedded::gc`. `Trace` requires `Finalize`, so derive both unless the type
+//! has a hand-written finalizer.
+//!
+//! Every field is traced unless it is marked `#[trace(skip)]`:
+//! - `Gc<_>` / `Weak<_>` fields call their own `trace`
+//! - `Option<Gc<_>>` and `Vec<Gc<_>>` trace each contained pointer
+//! - any other field must itself implement `Trace`
+//!
+//! `#[gc_trace]` instead checks a hand-written `impl Trace` at compile time.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Delimiter, TokenStream as TokenStream2, TokenTree};
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote_spanned, Data, DeriveInput, Field, GenericArgument, ImplItem,
+    Index, ItemImpl, Member, PathArguments, Token, Type,
+};
+
+#[proc_macro_derive(Trace, attributes(trace))]
+pub fn derive_trace(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Empty `Finalize` impl, for types that need no cleanup before being freed
+#[proc_macro_derive(Finalize)]
+pub fn derive_finalize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote!(impl #impl_generics ::embedded::gc::Finalize for #name #ty_generics #where_clause {})
+        .into()
+}
+
+/// Reject a hand-written `impl Trace` whose `trace` never mentions one of
+/// the struct's fields
+///
+/// Every `self.field` read in the body of `trace` counts as traced. Fields
+/// that hold no `Gc` are listed with `ignore`, so a newly added field always
+/// needs a decision. A missing field fails the build with "pattern does not
+/// mention field":
+///
+/// ```ignore
+/// #[gc_trace(ignore(value))]
+/// impl Trace for Node {
+///     fn trace(&self, tracer: &mut dyn Tracer) {
+///         self.left.trace(tracer); // error: `right` is not traced
+///     }
+///     fn as_any(&self) -> &dyn Any { self }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn gc_trace(args: TokenStream, input: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(input as ItemImpl);
+    expand_gc_trace(args.into(), item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_gc_trace(args: TokenStream2, mut item: ItemImpl) -> syn::Result<TokenStream2> {
+    let mut members: Vec<Member> = Vec::new();
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("ignore") {
+            let content;
+            syn::parenthesized!(content in meta.input);
+            members.extend(Punctuated::<Member, Token![,]>::parse_terminated(&content)?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `ignore(...)`"))
+        }
+    });
+    syn::parse::Parser::parse2(parser, args)?;
+
+    let span = item.self_ty.span();
+    let trace = item
+        .items
+        .iter_mut()
+        .find_map(|item| match item {
+            ImplItem::Fn(f) if f.sig.ident == "trace" => Some(f),
+            _ => None,
+        })
+        .ok_or_else(|| syn::Error::new(span, "#[gc_trace] needs an impl with a `trace` fn"))?;
+
+    let block = &trace.block;
+    collect_fields(quote!(#block), &mut members);
+
+    // Without `..`, the pattern only compiles if it names every field
+    trace.block.stmts.insert(
+        0,
+        parse_quote_spanned! {span=>
+            let Self { #(#members: _),* } = self;
+        },
+    );
+    Ok(quote!(#item))
+}
+
+/// Fields read as `self.field` or `self.0`, excluding method calls
+fn collect_fields(tokens: TokenStream2, found: &mut Vec<Member>) {
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            TokenTree::Group(group) => collect_fields(group.stream(), found),
+            TokenTree::Ident(ident) if ident == "self" => {
+                match tokens.get(i + 1) {
+                    Some(TokenTree::Punct(dot)) if dot.as_char() == '.' => {}
+                    _ => continue,
+                }
+                let is_call = match tokens.get(i + 3) {
+                    Some(TokenTree::Group(args)) => args.delimiter() == Delimiter::Parenthesis,
+                    // Turbofish
+                    Some(TokenTree::Punct(colon)) => colon.as_char() == ':',
+                    _ => false,
+                };
+                if is_call {
+                    continue;
+                }
+                let member = match tokens.get(i + 2) {
+                    Some(TokenTree::Ident(name)) => Member::Named(name.clone()),
+                    // `self.0.1` lexes as `self` `.` `0.1`
+                    Some(TokenTree::Literal(lit)) => {
+                        match lit
+                            .to_string()
+                            .split('.')
+                            .next()
+                            .and_then(|index| index.parse().ok())
+                        {
+                            Some(index) => Member::Unnamed(Index {
+                                index,
+                                span: lit.span(),
+                            }),
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                if !found.contains(&member) {
+                    found.push(member);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "#[derive(Trace)] only supports structs",
+            ))
+        }
+    };
+
+    let mut body = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        if is_skipped(field)? {
+            continue;
+        }
+        let access = match &field.ident {
+            Some(ident) => quote!(self.#ident),
+            None => {
+                let index = Index::from(index);
+                quote!(self.#index)
+            }
+        };
+        body.push(trace_field(&field.ty, access));
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::embedded::gc::Trace for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn trace(&self, tracer: &mut dyn ::embedded::gc::Tracer) {
+                #(#body)*
+            }
+
+            fn as_any(&self) -> &dyn ::core::any::Any {
+                self
+            }
+        }
+    })
+}
+
+fn is_skipped(field: &Field) -> syn::Result<bool> {
+    let mut skip = false;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("trace") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip`"))
+            }
+        })?;
+    }
+    Ok(skip)
+}
+
+fn trace_field(ty: &Type, access: TokenStream2) -> TokenStream2 {
+    if is_pointer(ty) {
+        return quote!(#access.trace(tracer););
+    }
+    if let Some(inner) = generic_arg(ty, "Option") {
+        if is_pointer(inner) {
+            return quote! {
+                if let ::core::option::Option::Some(ptr) = &#access {
+                    ptr.trace(tracer);
+                }
+            };
+        }
+    }
+    if let Some(inner) = generic_arg(ty, "Vec") {
+        if is_pointer(inner) {
+            return quote! {
+                for ptr in #access.iter() {
+                    ptr.trace(tracer);
+                }
+            };
+        }
+    }
+    quote!(::embedded::gc::Trace::trace(&#access, tracer);)
+}
+
+/// `Gc<_>` or `Weak<_>`, matched on the last path segment
+fn is_pointer(ty: &Type) -> bool {
+    generic_arg(ty, "Gc").is_some() || generic_arg(ty, "Weak").is_some()
+}
+
+/// The first type argument of `ty` if its last path segment is `name<...>`
+fn generic_arg<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let path = match ty {
+        Type::Path(path) if path.qself.is_none() => &path.path,
+        _ => return None,
+    };
+    let segment = path.segments.last()?;
+    if segment.ident != name {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
@@ -5,7 +5,7 @@ TARGET=thumbv7m-none-eabi
 PROJECT_NAME=embedded  # replace with your binary name
 
 echo "[*] Building project..."
-cargo build --release --target $TARGET
+cargo build --release --target $TARGET --features semihosting-log
 
 echo "[*] Running on QEMU..."
 qemu-system-arm \
@@ -1,62 +1,452 @@
 #![no_std]
 extern crate alloc;
 
-use core::cell::{Cell, RefCell};
-use core::ptr::NonNull;
-use core::any::Any;
+use core::cell::{Cell, Ref, RefCell, RefMut};
+use core::ptr::{self, NonNull};
+use core::alloc::Layout;
+use core::any::{Any, TypeId};
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+use core::mem::{self, size_of_val};
 use core::ops::Deref;
-use alloc::{boxed::Box, vec::Vec};
-use cortex_m_semihosting::hprintln;
+#[cfg(debug_assertions)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::alloc::{dealloc, handle_alloc_error};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Write a diagnostic line through a `GcLogger`
+macro_rules! log {
+    ($logger:expr, $($arg:tt)*) => {
+        $logger.log(format_args!($($arg)*))
+    };
+}
+
+#[cfg(feature = "derive")]
+pub use gc_derive::{gc_trace, Finalize, Trace};
+
+/// Cleanup hook run on unreachable objects before any of them is freed
+///
+/// Unlike `Drop`, which runs after other garbage may already have been
+/// dropped, a finalizer may still read other garbage objects through its
+/// `Gc` fields.
+pub trait Finalize {
+    fn finalize(&self) {}
+}
 
 /// Trait for GC-traceable objects
-pub trait Trace {
-    fn trace(&self);
+///
+/// Implementations report their direct `Gc` fields, by calling `trace` on
+/// them (or on containers holding them) or `Tracer::visit`. The collector's
+/// `MarkTracer` only marks and queues the pointee, so cycles and shared nodes
+/// are visited once. Never follow a pointer by calling `trace` on the
+/// pointee's value (`(*gc).trace`), as that bypasses the mark check and
+/// recurses forever on a cycle.
+pub trait Trace: Finalize {
+    /// Report every `Gc` held directly by `self` to `tracer`
+    fn trace(&self, tracer: &mut dyn Tracer);
     fn as_any(&self) -> &dyn Any;
+
+    /// Let go of the `Gc`s to other objects, e.g. by setting `Option<Gc<_>>`
+    /// fields to `None`
+    ///
+    /// Called on garbage after finalization and before anything is dropped,
+    /// so a `Drop` impl that follows its fields cannot reach a sibling that
+    /// is being dropped or was freed by an earlier `Heap::sweep_step`.
+    fn clear_refs(&mut self) {}
+
+    /// Copy of `self` for `Heap::deep_clone`, or `None` if the type cannot be
+    /// copied; a `TraceClone` type returns `Some(cloner.copy(self).unsize())`
+    fn clone_object(&self, _cloner: &mut Cloner<'_>) -> Option<Gc<dyn Trace>> {
+        None
+    }
+
+    /// Name of the concrete type, for `Heap::retain_stats`
+    #[cfg(debug_assertions)]
+    fn type_name(&self) -> &'static str {
+        core::any::type_name::<Self>()
+    }
+
+    /// Start and layout of the memory block holding this value's box, if its
+    /// type does not describe it; only for the slices of
+    /// `Heap::allocate_slice_from`, leave the default
+    #[doc(hidden)]
+    fn allocation(&self) -> Option<(NonNull<u8>, Layout)> {
+        None
+    }
+}
+
+/// Destination of the heap's diagnostics, see `Heap::set_logger`
+///
+/// Each call receives one line without its newline, e.g. for a UART or RTT
+/// channel.
+pub trait GcLogger {
+    fn log(&self, args: fmt::Arguments);
+}
+
+/// Discards every diagnostic
+pub struct NoopLogger;
+
+impl GcLogger for NoopLogger {
+    fn log(&self, _args: fmt::Arguments) {}
+}
+
+/// Prints diagnostics with `hprintln!`
+///
+/// Semihosting traps into the debugger and faults when none is attached, so
+/// production firmware must not use this logger.
+#[cfg(feature = "semihosting-log")]
+pub struct SemihostingLogger;
+
+#[cfg(feature = "semihosting-log")]
+impl GcLogger for SemihostingLogger {
+    fn log(&self, args: fmt::Arguments) {
+        cortex_m_semihosting::hprintln!("{}", args);
+    }
+}
+
+/// Receives the edges reported by `Trace::trace`
+///
+/// The heap marks with `MarkTracer`; other implementations can walk the
+/// object graph for their own purposes, e.g. counting edges or recording
+/// paths, by calling `trace` on a root themselves.
+pub trait Tracer {
+    fn visit(&mut self, gc: Gc<dyn Trace>);
+
+    /// Edge to a box that cannot be viewed as `dyn Trace`, such as a
+    /// `Gc<[T]>`, identified by its slot among the heap's objects (the index
+    /// in `Heap::iter_objects`). Ignored unless overridden.
+    fn visit_slot(&mut self, _slot: usize) {}
+
+    /// Diagnostic from a `trace` impl that could not report all of its
+    /// edges; the heap passes it to its `GcLogger`. Ignored unless overridden.
+    fn warn(&mut self, _args: fmt::Arguments) {}
+}
+
+const BITS_PER_WORD: usize = usize::BITS as usize;
+
+/// Mark bitmap and marking work-stack
+///
+/// Mark bits are indexed by each object's slot in `Heap::objects` and kept
+/// together here rather than in every `GcBox`. In tri-color terms an unmarked
+/// object is white, a marked one still on the stack is gray, and a marked one
+/// that has been scanned is black.
+///
+/// Reached objects are queued instead of being traced recursively, so marking
+/// a long chain does not grow the call stack.
+pub struct MarkTracer {
+    marks: Vec<usize>,
+    stack: Vec<NonNull<GcBox<dyn Trace>>>,
+    /// Gray objects known only by slot, see `Tracer::visit_slot`
+    pending: Vec<usize>,
+    /// Slots below this belong to the old generation, which a minor
+    /// collection treats as live without marking
+    floor: usize,
+    /// The heap's logger, for `Tracer::warn`
+    logger: &'static dyn GcLogger,
 }
 
-/// GC-managed data structure
-pub struct MyData {
-    pub value: i32,
-    pub child: Option<Gc<dyn Trace>>,
+impl MarkTracer {
+    const fn new(logger: &'static dyn GcLogger) -> Self {
+        MarkTracer {
+            marks: Vec::new(),
+            stack: Vec::new(),
+            pending: Vec::new(),
+            floor: 0,
+            logger,
+        }
+    }
+
+    /// Mark an unreached object and queue it for scanning
+    ///
+    /// The only way an edge is followed during collection: an object that is
+    /// already marked is ignored, so each one is scanned at most once.
+    fn mark(&mut self, ptr: NonNull<GcBox<dyn Trace>>) {
+        let slot = unsafe { ptr.as_ref().slot.get() };
+        if slot >= self.floor && !self.is_marked(slot) {
+            self.set_mark(slot);
+            self.stack.push(ptr);
+        }
+    }
+
+    /// Scan at most `max_objects` queued objects; returns `true` once none are left
+    fn scan(&mut self, objects: &[NonNull<GcBox<dyn Trace>>], max_objects: usize) -> bool {
+        for _ in 0..max_objects {
+            let ptr = match self.stack.pop() {
+                Some(ptr) => ptr,
+                None => match self.pending.pop() {
+                    Some(slot) => objects[slot],
+                    None => break,
+                },
+            };
+            unsafe { ptr.as_ref().value.trace(self) };
+        }
+        self.stack.is_empty() && self.pending.is_empty()
+    }
+
+    /// Drop all gray objects, e.g. to abandon an incremental collection
+    fn clear_stack(&mut self) {
+        self.stack.clear();
+        self.pending.clear();
+    }
+
+    fn is_marked(&self, slot: usize) -> bool {
+        self.marks[slot / BITS_PER_WORD] & (1 << (slot % BITS_PER_WORD)) != 0
+    }
+
+    fn set_mark(&mut self, slot: usize) {
+        self.marks[slot / BITS_PER_WORD] |= 1 << (slot % BITS_PER_WORD);
+    }
+
+    fn clear_mark(&mut self, slot: usize) {
+        self.marks[slot / BITS_PER_WORD] &= !(1 << (slot % BITS_PER_WORD));
+    }
+
+    /// Clear the mark bits of `from` and every later slot, a whole word at a
+    /// time instead of one object at a time
+    fn clear_marks_from(&mut self, from: usize) {
+        let word = from / BITS_PER_WORD;
+        if word < self.marks.len() {
+            self.marks[word] &= (1 << (from % BITS_PER_WORD)) - 1;
+            self.marks[word + 1..].fill(0);
+        }
+    }
+
+    /// Make room for the mark bit of `slot`
+    fn reserve_slot(&mut self, slot: usize) {
+        let words = slot / BITS_PER_WORD + 1;
+        if self.marks.len() < words {
+            self.marks.resize(words, 0);
+        }
+    }
 }
 
-impl Trace for MyData {
-    fn trace(&self) {
-        if let Some(child) = &self.child {
-            child.trace();
+impl Tracer for MarkTracer {
+    fn visit(&mut self, gc: Gc<dyn Trace>) {
+        self.mark(gc.ptr);
+    }
+
+    /// Such pointers carry no `dyn Trace` vtable, so the object is queued by
+    /// slot and looked up in `Heap::objects` when scanned
+    fn visit_slot(&mut self, slot: usize) {
+        if slot >= self.floor && !self.is_marked(slot) {
+            self.set_mark(slot);
+            self.pending.push(slot);
         }
     }
 
+    fn warn(&mut self, args: fmt::Arguments) {
+        self.logger.log(args);
+    }
+}
+
+/// Types that `Heap::deep_clone` can copy
+///
+/// Also override `Trace::clone_object` to return
+/// `Some(cloner.copy(self).unsize())`, so that the copy can be made through
+/// a `Gc<dyn Trace>`.
+pub trait TraceClone: Trace + Sized + 'static {
+    /// Copy `self`, passing every `Gc` field through `cloner.clone_gc` or
+    /// `cloner.clone_dyn`
+    fn clone_with(&self, cloner: &mut Cloner<'_>) -> Self;
+}
+
+/// Marker for types that hold no `Gc`, such as plain sensor readings
+///
+/// Implementing it provides `Trace` with an empty `trace` and a no-op
+/// `Finalize`, so such types can be allocated without either impl.
+pub trait TraceLeaf: 'static {}
+
+impl<T: TraceLeaf> Finalize for T {}
+
+impl<T: TraceLeaf> Trace for T {
+    fn trace(&self, _tracer: &mut dyn Tracer) {}
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
 
-impl Drop for MyData {
-    fn drop(&mut self) {
-        hprintln!("Dropping MyData with value = {}", self.value);
+macro_rules! trace_leaf {
+    ($($ty:ty),*) => {
+        $(impl TraceLeaf for $ty {})*
+    };
+}
+
+trace_leaf!(
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    ()
+);
+
+impl<T> Finalize for Option<T> {}
+
+impl<T: Trace + 'static> Trace for Option<T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        if let Some(value) = self {
+            value.trace(tracer);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<T> Finalize for Vec<T> {}
+
+impl<T: Trace + 'static> Trace for Vec<T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        for value in self {
+            value.trace(tracer);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<T, const N: usize> Finalize for [T; N] {}
+
+impl<T: Trace + 'static, const N: usize> Trace for [T; N] {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        for value in self {
+            value.trace(tracer);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<T> Finalize for VecDeque<T> {}
+
+impl<T: Trace + 'static> Trace for VecDeque<T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        for value in self {
+            value.trace(tracer);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<T> Finalize for RefCell<T> {}
+
+/// Plain `RefCell` fields are traced too, but unlike `GcCell` a value that is
+/// mutably borrowed during a collection is skipped with a warning instead of
+/// panicking, so objects reachable only through it may be freed.
+impl<T: Trace + 'static> Trace for RefCell<T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        match self.try_borrow() {
+            Ok(value) => value.trace(tracer),
+            Err(_) => tracer.warn(format_args!(
+                "gc: not tracing a mutably borrowed RefCell<{}>",
+                core::any::type_name::<T>()
+            )),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl<K, V> Finalize for BTreeMap<K, V> {}
+
+/// Only the values are traced, so keys can be plain data such as integers
+impl<K: 'static, V: Trace + 'static> Trace for BTreeMap<K, V> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        for value in self.values() {
+            value.trace(tracer);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
-/// Box that stores traced object and mark bit
+/// Box that stores a traced object and its slot in the heap
+///
+/// The value is stored inline as the last field so that a `GcBox<T>` can be
+/// unsized into a `GcBox<dyn Trace>` without losing the vtable. `repr(C)`
+/// puts it at the same offset in a `GcBox<[T]>` as in a
+/// `GcBox<SliceElems<T>>`, see `Heap::allocate_slice_from`.
+#[repr(C)]
 pub struct GcBox<T: ?Sized> {
-    pub marked: Cell<bool>,
-    pub value: Box<T>,
+    /// Index into `Heap::objects` and the mark bitmap; renumbered by sweeps
+    slot: Cell<usize>,
+    /// Outstanding `Counted` handles; the object is a root while nonzero
+    strong: Cell<usize>,
+    /// Concrete type of `value`, checked by `Gc::downcast_ref`
+    type_id: TypeId,
+    /// `Heap::id` of the owning heap, to catch mixing up heaps
+    #[cfg(debug_assertions)]
+    heap_id: Cell<usize>,
+    /// Minor collections survived while in the young generation
+    age: Cell<u8>,
+    pub value: T,
 }
 
-impl<T: ?Sized> GcBox<T> {
-    pub fn new(value: Box<T>) -> Self {
+impl<T: 'static> GcBox<T> {
+    pub fn new(value: T) -> Self {
+        GcBox::with_type_id(value, TypeId::of::<T>())
+    }
+}
+
+impl<T> GcBox<T> {
+    /// `type_id` must be `T`'s, or that of a type no downcast asks for
+    fn with_type_id(value: T, type_id: TypeId) -> Self {
         GcBox {
-            marked: Cell::new(false),
+            slot: Cell::new(0),
+            age: Cell::new(0),
+            strong: Cell::new(0),
+            type_id,
+            #[cfg(debug_assertions)]
+            heap_id: Cell::new(0),
             value,
         }
     }
+}
+
+impl<T: ?Sized> GcBox<T> {
+    /// `TypeId` of the concrete type the box was allocated with
+    pub fn value_type_id(&self) -> TypeId {
+        self.type_id
+    }
+}
 
-    fn trace(&self)
-    where
-        T: Trace,
-    {
-        self.value.trace();
+/// Mark bits live in the heap, so only the slot and generation age are shown
+impl<T: fmt::Debug + ?Sized> fmt::Debug for GcBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcBox")
+            .field("slot", &self.slot.get())
+            .field("age", &self.age.get())
+            .field("value", &&self.value)
+            .finish()
     }
 }
 
@@ -72,129 +462,5518 @@ impl<T: ?Sized> Clone for Gc<T> {
     }
 }
 
+/// The short-lived path: nothing ties the returned reference to the object
+/// staying alive, so it must not be held across anything that may collect
+/// (`allocate`, `collect`, ...). `Heap::access` enforces that.
 impl<T: ?Sized> Deref for Gc<T> {
     type Target = T;
     fn deref(&self) -> &T {
-        unsafe { &*self.ptr.as_ref().value }
+        unsafe { &self.ptr.as_ref().value }
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Gc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+/// Sound for map lookups because `Eq` and `Hash` also go by the pointee
+impl<T: ?Sized> Borrow<T> for Gc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+/// `Gc(0x20000123 -> value)`
+impl<T: fmt::Debug + ?Sized> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Gc({:#x} -> {:?})",
+            self.ptr.as_ptr() as *const u8 as usize,
+            &**self
+        )
+    }
+}
+
+/// Compares the pointees; use `Gc::ptr_eq` to ask whether two `Gc`s are the
+/// same object
+impl<T: PartialEq + ?Sized> PartialEq for Gc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq + ?Sized> Eq for Gc<T> {}
+
+/// Hashes the pointee, consistent with `PartialEq`
+impl<T: Hash + ?Sized> Hash for Gc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
     }
 }
 
-impl<T: Trace + ?Sized> Gc<T> {
+impl<T: ?Sized> Gc<T> {
+    /// Wrap a pointer to a tracked box, e.g. one obtained from `as_ptr` and
+    /// unsized to another trait object
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point at a box the heap still tracks; dereferencing the
+    /// result after that box is freed is undefined behavior.
     pub unsafe fn from_raw(ptr: NonNull<GcBox<T>>) -> Self {
         Gc { ptr }
     }
 
+    /// The box's address; cast it to `usize` to get one back with `from_addr`
+    pub fn as_ptr(&self) -> NonNull<GcBox<T>> {
+        self.ptr
+    }
+
+    /// Whether both handles point at the same allocation
+    ///
+    /// Only addresses are compared; for `Gc<dyn Trace>` the vtable is ignored.
+    pub fn ptr_eq(a: &Gc<T>, b: &Gc<T>) -> bool {
+        core::ptr::addr_eq(a.ptr.as_ptr(), b.ptr.as_ptr())
+    }
+
+    /// A `Weak` to the same object, leaving rooting and marking unchanged
+    ///
+    /// Unlike `Rc::downgrade`, this takes the heap: all `Weak`s to an object
+    /// share one liveness flag, which the heap lists so that the sweep
+    /// freeing the object can clear it, and both finding that flag and
+    /// listing a new one go through the heap. Allocating the flag lazily
+    /// instead would need a pointer to it in every `GcBox` header; see
+    /// `Weak::new`.
+    pub fn downgrade(&self, heap: &mut Heap) -> Weak<T> {
+        Weak::new(heap, Gc { ptr: self.ptr })
+    }
+}
+
+impl<T: Trace + 'static> Gc<T> {
+    /// Allocate `value` in `heap`, like `Box::new`; same as `heap.allocate(value)`
+    pub fn new_in(value: T, heap: &mut Heap) -> Gc<T> {
+        heap.allocate(value)
+    }
+
+    /// Erase the concrete type, e.g. to store the handle in a `Gc<dyn Trace>` field
+    pub fn unsize(self) -> Gc<dyn Trace> {
+        Gc { ptr: self.ptr }
+    }
+
     pub fn as_non_null(&self) -> NonNull<GcBox<dyn Trace>> {
-        to_dyn_trace_ptr(self.ptr)
+        self.ptr
     }
 
-    pub fn trace(&self) {
-        unsafe {
-            let gc_box = self.ptr.as_ref();
-            if !gc_box.marked.get() {
-                gc_box.marked.set(true);
-                gc_box.value.trace();
-            }
+    pub fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit(self.unsize());
+    }
+}
+
+impl Gc<dyn Trace> {
+    pub fn as_non_null(&self) -> NonNull<GcBox<dyn Trace>> {
+        self.ptr
+    }
+
+    pub fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit(*self);
+    }
+
+    /// Rebuild a handle from an address saved with `as_ptr`, e.g. one passed
+    /// through a C callback
+    ///
+    /// Returns `None` unless `addr` is the start of one of `heap`'s live
+    /// objects, so a stale address is caught as long as its memory was not
+    /// handed to a new object.
+    ///
+    /// # Safety
+    ///
+    /// If the object at `addr` may have been freed, the caller must rule out
+    /// that another object now occupies the same address.
+    pub unsafe fn from_addr(addr: usize, heap: &Heap) -> Option<Gc<dyn Trace>> {
+        heap.objects
+            .iter()
+            .find(|obj| obj.as_ptr() as *const u8 as usize == addr)
+            .map(|&ptr| Gc { ptr })
+    }
+
+    /// Borrow the pointee as a concrete `T`, or `None` if it is another type
+    ///
+    /// Compares the type recorded at allocation, without calling `as_any`.
+    pub fn downcast_ref<T: Trace + 'static>(&self) -> Option<&T> {
+        let obj = unsafe { self.ptr.as_ref() };
+        if obj.type_id == TypeId::of::<T>() {
+            Some(unsafe { &*(&obj.value as *const dyn Trace as *const T) })
+        } else {
+            None
         }
     }
+
+    /// Reinterpret as a handle to a concrete `U`, skipping the type check of
+    /// `downcast_ref`
+    ///
+    /// # Safety
+    ///
+    /// The object must have been allocated as a `U`, e.g. as confirmed by an
+    /// earlier `downcast_ref::<U>()`; otherwise using the result is undefined
+    /// behavior.
+    pub unsafe fn cast_unchecked<U: Trace + 'static>(&self) -> Gc<U> {
+        Gc {
+            ptr: self.ptr.cast(),
+        }
+    }
+
+    /// Downcast to `T` and borrow the part of it selected by `f`, e.g. a field
+    ///
+    /// As with `Deref`, the borrow is tied to this handle and must not be
+    /// held across a collection that could free the object.
+    pub fn project<T, U, F>(&self, f: F) -> Option<&U>
+    where
+        T: Trace + 'static,
+        U: ?Sized,
+        F: FnOnce(&T) -> &U,
+    {
+        self.downcast_ref::<T>().map(f)
+    }
 }
 
-/// Convert GcBox<T> to GcBox<dyn Trace>
-pub fn to_dyn_trace_ptr<T: Trace + ?Sized>(ptr: NonNull<GcBox<T>>) -> NonNull<GcBox<dyn Trace>> {
-    unsafe { NonNull::new_unchecked(ptr.as_ptr() as *mut GcBox<dyn Trace>) }
+impl<T: Trace + 'static> Gc<[T]> {
+    pub fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit_slot(unsafe { self.ptr.as_ref().slot.get() });
+    }
 }
 
-/// The Heap tracks all allocations and roots
-pub struct Heap {
-    objects: Vec<NonNull<GcBox<dyn Trace>>>,
-    pub roots: RefCell<Vec<NonNull<GcBox<dyn Trace>>>>,
-    allocation_count: usize,
-    threshold: usize,
+impl<T: Trace + 'static> From<Gc<T>> for Gc<dyn Trace> {
+    fn from(gc: Gc<T>) -> Self {
+        gc.unsize()
+    }
 }
 
-impl Heap {
-    pub fn new() -> Self {
-        Heap {
-            objects: Vec::new(),
-            roots: RefCell::new(Vec::new()),
-            allocation_count: 0,
-            threshold: 1,
-        }
+impl<T: ?Sized> Finalize for Gc<T> {}
+
+/// A `Gc` is traced by reporting its pointee, so a type holding one (or a
+/// container of them) can forward to it like to any other field
+///
+/// `Gc<dyn Trace>` and `Gc<[T]>` have impls of their own rather than one
+/// `T: ?Sized` impl, as only a sized `T` can be unsized to `dyn Trace`.
+impl<T: Trace + 'static> Trace for Gc<T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit(self.unsize());
     }
 
-    pub fn allocate<T: Trace + 'static>(&mut self, value: T) -> Gc<dyn Trace> {
-        let boxed: Box<dyn Trace> = Box::new(value);
-        let gc_box = Box::new(GcBox::new(boxed));
-        let ptr = unsafe { NonNull::new_unchecked(Box::into_raw(gc_box)) };
-        self.objects.push(ptr);
-        self.allocation_count += 1;
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
 
-        if self.allocation_count >= self.threshold {
-            let roots = self.roots.borrow().clone();
-            self.collect_garbage(&roots);
-            self.allocation_count = 0;
-        }
+impl Trace for Gc<dyn Trace> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit(*self);
+    }
 
-        unsafe { Gc::from_raw(ptr) }
+    fn as_any(&self) -> &dyn Any {
+        self
     }
+}
 
-    pub fn register_root(&self, ptr: NonNull<GcBox<dyn Trace>>) {
-        let mut roots = self.roots.borrow_mut();
-        if !roots.contains(&ptr) {
-            roots.push(ptr);
-        }
+/// The elements are traced by the box itself, which is a `[T; N]` or a
+/// `SliceElems<T>`
+impl<T: Trace + 'static> Trace for Gc<[T]> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit_slot(unsafe { self.ptr.as_ref().slot.get() });
     }
 
-    pub fn unregister_root(&self, ptr: NonNull<GcBox<dyn Trace>>) {
-        let mut roots = self.roots.borrow_mut();
-        roots.retain(|&r| r != ptr);
+    fn as_any(&self) -> &dyn Any {
+        self
     }
+}
 
-    pub fn collect_garbage(&mut self, roots: &[NonNull<GcBox<dyn Trace>>]) {
-        for obj in &self.objects {
-            unsafe {
-                obj.as_ref().marked.set(false);
-            }
+/// The heap's view of a slice from `Heap::allocate_slice_from`
+///
+/// Stands in for the `[T]` at the end of its `GcBox`, whose length is kept
+/// in the word just before the box, so the elements are traced and dropped
+/// one by one like those of a `[T; N]`.
+struct SliceElems<T>([T; 0]);
+
+impl<T> SliceElems<T> {
+    /// Offset of the first element from the start of the box
+    const VALUE_OFFSET: usize = mem::offset_of!(GcBox<SliceElems<T>>, value);
+
+    /// Block for a box of `len` elements and the offset of the box in it
+    fn block_layout(len: usize) -> (Layout, usize) {
+        let boxed = Layout::array::<T>(len)
+            .and_then(|elems| {
+                let size = Self::VALUE_OFFSET.checked_add(elems.size());
+                Layout::from_size_align(size.unwrap_or(usize::MAX), mem::align_of::<GcBox<Self>>())
+            })
+            .expect("gc: slice too large");
+        let (block, offset) = Layout::new::<usize>()
+            .extend(boxed.pad_to_align())
+            .expect("gc: slice too large");
+        (block.pad_to_align(), offset)
+    }
+
+    fn box_start(&self) -> *mut u8 {
+        (self as *const Self as *mut u8).wrapping_sub(Self::VALUE_OFFSET)
+    }
+
+    fn elems(&self) -> *mut [T] {
+        let len = unsafe { *(self.box_start() as *const usize).sub(1) };
+        ptr::slice_from_raw_parts_mut(self as *const Self as *mut T, len)
+    }
+}
+
+impl<T> Drop for SliceElems<T> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.elems()) };
+    }
+}
+
+impl<T> Finalize for SliceElems<T> {}
+
+impl<T: Trace + 'static> Trace for SliceElems<T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        for value in unsafe { &*self.elems() } {
+            value.trace(tracer);
         }
+    }
 
-        for &root in roots {
-            unsafe {
-                let obj = root.as_ref();
-                if !obj.marked.get() {
-                    obj.marked.set(true);
-                    obj.value.trace();
-                }
-            }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn clear_refs(&mut self) {
+        for value in unsafe { &mut *self.elems() } {
+            value.clear_refs();
         }
+    }
 
-        self.objects.retain(|&ptr| {
-            let keep = unsafe { ptr.as_ref().marked.get() };
-            if !keep {
-                unsafe {
-                    drop(Box::from_raw(ptr.as_ptr()));
-                }
-            }
-            keep
-        });
+    #[cfg(debug_assertions)]
+    fn type_name(&self) -> &'static str {
+        core::any::type_name::<[T]>()
+    }
+
+    fn allocation(&self) -> Option<(NonNull<u8>, Layout)> {
+        let len = self.elems().len();
+        let (layout, offset) = Self::block_layout(len);
+        Some((
+            unsafe { NonNull::new_unchecked(self.box_start().sub(offset)) },
+            layout,
+        ))
     }
 }
 
-/// RAII root registration
-pub struct RootGuard<'a> {
-    heap: &'a Heap,
-    ptr: NonNull<GcBox<dyn Trace>>,
+/// Weak GC pointer: does not keep its target alive
+///
+/// Tracing a `Weak` is a no-op, so back-pointers (e.g. parent links) can be
+/// stored without the collector following them.
+pub struct Weak<T: ?Sized> {
+    ptr: NonNull<GcBox<T>>,
+    flag: NonNull<WeakFlag>,
 }
 
-impl<'a> RootGuard<'a> {
-    pub fn new(heap: &'a Heap, gc: Gc<dyn Trace>) -> Self {
-        let ptr = gc.as_non_null();
-        heap.register_root(ptr);
-        RootGuard { heap, ptr }
+/// Liveness of a weak target, shared by a `Weak` and its clones
+///
+/// Listed in `Heap::weaks` and cleared by the sweep that frees the target.
+/// Freed by whichever of the heap and the last `Weak` lets go of it last.
+struct WeakFlag {
+    /// Slot of the target, read through the target itself so it is always
+    /// up to date; only valid while `alive`
+    target_slot: NonNull<Cell<usize>>,
+    alive: Cell<bool>,
+    weak_count: Cell<usize>,
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        let flag = unsafe { self.flag.as_ref() };
+        flag.weak_count.set(flag.weak_count.get() + 1);
+        Weak {
+            ptr: self.ptr,
+            flag: self.flag,
+        }
     }
 }
 
-impl<'a> Drop for RootGuard<'a> {
+impl<T: ?Sized> Drop for Weak<T> {
     fn drop(&mut self) {
-        self.heap.unregister_root(self.ptr);
+        let flag = unsafe { self.flag.as_ref() };
+        flag.weak_count.set(flag.weak_count.get() - 1);
+        // While the target lives the heap still lists the flag and frees it
+        if flag.weak_count.get() == 0 && !flag.alive.get() {
+            drop(unsafe { Box::from_raw(self.flag.as_ptr()) });
+        }
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Weak reference to `gc`, sharing the liveness flag of any other
+    /// outstanding `Weak` to it
+    pub fn new(heap: &mut Heap, gc: Gc<T>) -> Self {
+        let target_slot = NonNull::from(unsafe { &gc.ptr.as_ref().slot });
+        // Listed flags all belong to live objects
+        let shared = heap
+            .weaks
+            .iter()
+            .find(|flag| unsafe { flag.as_ref().target_slot } == target_slot);
+        if let Some(&flag) = shared {
+            let count = unsafe { &flag.as_ref().weak_count };
+            count.set(count.get() + 1);
+            return Weak { ptr: gc.ptr, flag };
+        }
+
+        let flag = Box::new(WeakFlag {
+            target_slot,
+            alive: Cell::new(true),
+            weak_count: Cell::new(1),
+        });
+        let flag = unsafe { NonNull::new_unchecked(Box::into_raw(flag)) };
+        heap.weaks.push(flag);
+        Weak { ptr: gc.ptr, flag }
+    }
+
+    /// Whether the target has not been swept yet
+    pub fn is_alive(&self) -> bool {
+        unsafe { self.flag.as_ref().alive.get() }
+    }
+
+    /// Returns the strong pointer if the target has not been swept
+    pub fn upgrade(&self) -> Option<Gc<T>> {
+        if self.is_alive() {
+            Some(Gc { ptr: self.ptr })
+        } else {
+            None
+        }
+    }
+
+    pub fn trace(&self, _tracer: &mut dyn Tracer) {}
+}
+
+impl<T: ?Sized> Finalize for Weak<T> {}
+
+impl<T: ?Sized + 'static> Trace for Weak<T> {
+    fn trace(&self, _tracer: &mut dyn Tracer) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Interior mutability for fields of GC-managed objects
+///
+/// `Gc` only hands out shared references; wrap fields that need to change
+/// after allocation (e.g. a child pointer) in a `GcCell`. A cell knows
+/// neither the object holding it nor its heap, so `borrow_mut` is handed
+/// both to run the write barrier.
+pub struct GcCell<T> {
+    value: RefCell<T>,
+}
+
+impl<T> GcCell<T> {
+    pub fn new(value: T) -> Self {
+        GcCell {
+            value: RefCell::new(value),
+        }
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.value.borrow()
+    }
+
+    /// Mutably borrow the value of this cell inside `owner`, which may then
+    /// point at other objects; see `Heap::write_barrier`
+    ///
+    /// Panics unless `owner` is a live object of `heap`.
+    pub fn borrow_mut<O: ?Sized>(&self, heap: &mut Heap, owner: &Gc<O>) -> RefMut<'_, T> {
+        heap.write_barrier(heap.object_of(owner));
+        self.value.borrow_mut()
+    }
+}
+
+impl<T> Finalize for GcCell<T> {}
+
+impl<T: Trace + 'static> Trace for GcCell<T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        // Panics if a `borrow_mut` is held across a collection
+        self.value.borrow().trace(tracer);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Slot-table entry holding the current address of a relocatable object
+struct HandleSlot {
+    ptr: Cell<NonNull<GcBox<dyn Trace>>>,
+    /// Rebuilds the erased pointer (with `T`'s vtable) at a new address
+    erase: fn(NonNull<u8>) -> NonNull<GcBox<dyn Trace>>,
+}
+
+fn erase_at<T: Trace + 'static>(ptr: NonNull<u8>) -> NonNull<GcBox<dyn Trace>> {
+    ptr.cast::<GcBox<T>>()
+}
+
+/// Relocatable GC pointer, created by `Heap::allocate_handle`
+///
+/// A handle points at a slot-table entry instead of the object itself, so
+/// `Heap::compact` may move the object and only update the slot. Objects
+/// allocated this way are never exposed as a plain `Gc`.
+pub struct GcHandle<T: ?Sized> {
+    slot: NonNull<HandleSlot>,
+    _marker: PhantomData<*const T>,
+}
+
+impl<T: ?Sized> Copy for GcHandle<T> {}
+impl<T: ?Sized> Clone for GcHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Trace + 'static> GcHandle<T> {
+    /// Current address of the object; changes when the heap is compacted
+    pub fn as_non_null(&self) -> NonNull<GcBox<dyn Trace>> {
+        unsafe { self.slot.as_ref().ptr.get() }
+    }
+
+    pub fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit(unsafe { Gc::from_raw(self.as_non_null()) });
+    }
+}
+
+impl<T: Trace + 'static> Deref for GcHandle<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &self.as_non_null().cast::<GcBox<T>>().as_ref().value }
+    }
+}
+
+impl<T: ?Sized> Finalize for GcHandle<T> {}
+
+impl<T: Trace + 'static> Trace for GcHandle<T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit(unsafe { Gc::from_raw(self.as_non_null()) });
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Convert GcBox<T> to GcBox<dyn Trace>
+///
+/// This is an unsizing coercion, so the vtable always belongs to `T`. Never
+/// build a `GcBox<dyn Trace>` pointer with an `as` cast.
+pub fn to_dyn_trace_ptr<T: Trace + 'static>(ptr: NonNull<GcBox<T>>) -> NonNull<GcBox<dyn Trace>> {
+    ptr
+}
+
+/// Freed `GcBox` slots kept for reuse are capped at this many
+const FREE_LIST_LIMIT: usize = 16;
+
+/// Objects marked or swept between clock checks in `collect_with_budget`
+#[cfg(feature = "cycle-count")]
+const BUDGET_CHUNK: usize = 16;
+
+/// Collections averaged by `Heap::allocation_rate`
+const RATE_SAMPLES: usize = 8;
+
+/// Memory of a swept `GcBox`, cached for the next allocation of the same layout
+struct FreeSlot {
+    layout: Layout,
+    ptr: NonNull<u8>,
+}
+
+/// Source of `Heap::id`; 0 is never handed out
+#[cfg(debug_assertions)]
+static NEXT_HEAP_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Receives a `Weak` to every survivor, see `Heap::set_weak_observer`
+type WeakObserver = fn(Vec<Weak<dyn Trace>>);
+
+/// The Heap tracks all allocations and roots
+pub struct Heap {
+    /// Distinguishes heaps in the debug checks against mixing them up;
+    /// 0 until first needed, so that `new` can be `const`
+    #[cfg(debug_assertions)]
+    id: Cell<usize>,
+    /// Old generation first (`..old_count`), then the young generation
+    objects: Vec<NonNull<GcBox<dyn Trace>>>,
+    old_count: usize,
+    /// Old objects that may point into the young generation
+    remembered: Vec<NonNull<GcBox<dyn Trace>>>,
+    /// Minor collections a young object survives before promotion
+    /// (0 disables generations, so every collection is a major one)
+    promotion_age: u8,
+    /// Old generation size that triggers the next major collection
+    major_threshold: usize,
+    min_major_threshold: usize,
+    /// Registered roots, sorted by address
+    roots: Vec<NonNull<GcBox<dyn Trace>>>,
+    /// Number of registrations of each entry of `roots`; 0 marks a
+    /// tombstone left by `unregister_root` until `purge_roots`
+    root_counts: Vec<usize>,
+    root_tombstones: usize,
+    /// Buffer the roots of a collection are copied into
+    root_scratch: Vec<NonNull<GcBox<dyn Trace>>>,
+    /// Roots that `unregister_root` leaves alone, see `pin_root`
+    pinned: Vec<NonNull<GcBox<dyn Trace>>>,
+    allocation_count: usize,
+    mode: CollectionMode,
+    threshold: usize,
+    /// Lower bound for `threshold` as configured by the user
+    min_threshold: usize,
+    /// After a collection, `threshold` becomes `survivors * growth_factor`
+    /// (0 keeps it fixed)
+    growth_factor: usize,
+    /// Shrink the heap's buffers after a collection that frees more than
+    /// this percentage of the objects (0 never does)
+    shrink_percent: usize,
+    /// Allocations since the last collection of any kind
+    allocations_since_collection: usize,
+    /// `allocations_since_collection` at the end of recent collections, a
+    /// ring buffer written at `rate_next`
+    rate_samples: [usize; RATE_SAMPLES],
+    rate_next: usize,
+    /// Number of valid `rate_samples`
+    rate_filled: usize,
+    /// Set `threshold` from `allocation_rate` instead of `growth_factor`
+    adaptive_threshold: bool,
+    /// Gray objects awaiting a scan, kept across collections to reuse its buffer
+    tracer: MarkTracer,
+    /// Garbage of the current sweep and its layouts, emptied after every
+    /// sweep but kept to reuse its buffer
+    doomed: Vec<Doomed>,
+    /// An incremental collection is in progress
+    collecting: bool,
+    /// Progress of a sweep driven by `sweep_step`
+    sweep: Option<SweepState>,
+    free_list: Vec<FreeSlot>,
+    /// Allocations served from `free_list` instead of the global allocator
+    reused_slots: usize,
+    /// Slot table behind every live `GcHandle`
+    handles: Vec<NonNull<HandleSlot>>,
+    /// Flags of the targets of outstanding `Weak`s
+    weaks: Vec<NonNull<WeakFlag>>,
+    on_collect_start: Option<fn()>,
+    /// Completed collections since the heap was created
+    collection_seq: usize,
+    /// Clock read at the end of every collection, see `set_collection_clock`
+    collection_clock: Option<fn() -> u32>,
+    last_collection_tick: Option<u32>,
+    on_collect_end: Option<fn(&CollectionStats)>,
+    weak_observer: Option<WeakObserver>,
+    /// Region kept for reuse by the next `bump_scope`
+    bump_region: Option<(NonNull<u8>, Layout)>,
+    /// Bytes in use in the backing allocator, see `set_allocator_stats`
+    allocator_used: Option<fn() -> usize>,
+    /// Stack region scanned for conservative roots
+    stack_bounds: Option<fn() -> (*const u8, *const u8)>,
+    logger: &'static dyn GcLogger,
+    /// Lists filled by the sweep during `retain_stats`
+    #[cfg(debug_assertions)]
+    retain_log: Option<RetainLog>,
+    /// Cycle counter read around stop-the-world collections
+    #[cfg(feature = "cycle-count")]
+    clock: fn() -> u32,
+    #[cfg(feature = "cycle-count")]
+    last_pause_cycles: u32,
+}
+
+impl Heap {
+    /// An empty heap; `const`, so it can initialize a `static`, e.g.
+    /// through `GlobalHeap::new`
+    pub const fn new() -> Self {
+        #[cfg(feature = "semihosting-log")]
+        let logger: &'static dyn GcLogger = &SemihostingLogger;
+        #[cfg(not(feature = "semihosting-log"))]
+        let logger: &'static dyn GcLogger = &NoopLogger;
+        Heap {
+            #[cfg(debug_assertions)]
+            id: Cell::new(0),
+            objects: Vec::new(),
+            old_count: 0,
+            remembered: Vec::new(),
+            promotion_age: 0,
+            major_threshold: 0,
+            min_major_threshold: 0,
+            roots: Vec::new(),
+            root_counts: Vec::new(),
+            root_tombstones: 0,
+            root_scratch: Vec::new(),
+            pinned: Vec::new(),
+            allocation_count: 0,
+            mode: CollectionMode::Threshold(1),
+            threshold: 1,
+            min_threshold: 1,
+            growth_factor: 2,
+            shrink_percent: 0,
+            allocations_since_collection: 0,
+            rate_samples: [0; RATE_SAMPLES],
+            rate_next: 0,
+            rate_filled: 0,
+            adaptive_threshold: false,
+            tracer: MarkTracer::new(logger),
+            doomed: Vec::new(),
+            collecting: false,
+            sweep: None,
+            free_list: Vec::new(),
+            reused_slots: 0,
+            handles: Vec::new(),
+            weaks: Vec::new(),
+            on_collect_start: None,
+            collection_seq: 0,
+            collection_clock: None,
+            last_collection_tick: None,
+            on_collect_end: None,
+            weak_observer: None,
+            allocator_used: None,
+            bump_region: None,
+            stack_bounds: None,
+            logger,
+            #[cfg(debug_assertions)]
+            retain_log: None,
+            #[cfg(feature = "cycle-count")]
+            clock: cortex_m::peripheral::DWT::cycle_count,
+            #[cfg(feature = "cycle-count")]
+            last_pause_cycles: 0,
+        }
+    }
+
+    pub fn with_threshold(threshold: usize) -> Self {
+        let mut heap = Heap::new();
+        heap.set_threshold(threshold);
+        heap
+    }
+
+    /// Heap with room for `objects` objects and `roots` distinct roots
+    ///
+    /// Staying within those bounds never reallocates the object table, its
+    /// mark bits or the root set.
+    pub fn with_capacity(objects: usize, roots: usize) -> Self {
+        let mut heap = Heap::new();
+        heap.objects.reserve_exact(objects);
+        heap.tracer
+            .marks
+            .reserve_exact(objects.div_ceil(BITS_PER_WORD));
+        heap.roots.reserve_exact(roots);
+        heap.root_counts.reserve_exact(roots);
+        heap
+    }
+
+    /// Number of objects the heap can track without growing its object table
+    pub fn capacity(&self) -> usize {
+        self.objects.capacity()
+    }
+
+    /// Cycles spent in the last stop-the-world collection
+    ///
+    /// Read from the DWT cycle counter by default, which must be enabled
+    /// first (`DCB::enable_trace` and `DWT::enable_cycle_counter`).
+    #[cfg(feature = "cycle-count")]
+    pub fn last_pause_cycles(&self) -> u32 {
+        self.last_pause_cycles
+    }
+
+    /// Replace the DWT cycle counter, e.g. with another timer
+    #[cfg(feature = "cycle-count")]
+    pub fn set_cycle_clock(&mut self, clock: fn() -> u32) {
+        self.clock = clock;
+    }
+
+    /// Number of collections completed so far, of any kind
+    pub fn collection_count(&self) -> usize {
+        self.collection_seq
+    }
+
+    /// Record `clock()` at the end of every collection, e.g. a millisecond
+    /// tick, to rate-limit collections with `last_collection_tick`
+    pub fn set_collection_clock(&mut self, clock: fn() -> u32) {
+        self.collection_clock = Some(clock);
+    }
+
+    /// Clock reading at the end of the last collection, if a clock is set
+    /// and a collection finished since
+    pub fn last_collection_tick(&self) -> Option<u32> {
+        self.last_collection_tick
+    }
+
+    /// Number of allocations between automatic collections
+    pub fn set_threshold(&mut self, threshold: usize) {
+        let threshold = threshold.max(1);
+        self.threshold = threshold;
+        self.min_threshold = threshold;
+        if let CollectionMode::Threshold(configured) = &mut self.mode {
+            *configured = threshold;
+        }
+    }
+
+    /// Choose when allocations trigger a collection
+    ///
+    /// `Threshold(n)` is the default and the same as `set_threshold(n)`.
+    pub fn set_mode(&mut self, mode: CollectionMode) {
+        self.mode = mode;
+        if let CollectionMode::Threshold(threshold) = mode {
+            self.set_threshold(threshold);
+        }
+    }
+
+    pub fn mode(&self) -> CollectionMode {
+        self.mode
+    }
+
+    /// Whether an allocation should collect, with `upcoming` more
+    /// allocations counted than have happened
+    fn collection_due(&self, upcoming: usize) -> bool {
+        match self.mode {
+            CollectionMode::Eager => true,
+            CollectionMode::Threshold(_) => self.allocation_count + upcoming >= self.threshold,
+            CollectionMode::Manual => false,
+        }
+    }
+
+    pub fn set_growth_factor(&mut self, growth_factor: usize) {
+        self.growth_factor = growth_factor;
+    }
+
+    /// Average number of allocations between the last few collections, of
+    /// any kind; 0 before the first one
+    pub fn allocation_rate(&self) -> usize {
+        if self.rate_filled == 0 {
+            return 0;
+        }
+        self.rate_samples[..self.rate_filled].iter().sum::<usize>() / self.rate_filled
+    }
+
+    /// After every collection, set the threshold to `allocation_rate`
+    /// (but not below the one passed to `set_threshold`) instead of scaling
+    /// it with `growth_factor`
+    ///
+    /// Automatic collections then come about as often as the recent
+    /// collections did, however they were triggered, so e.g. one collection
+    /// per main-loop iteration stays the norm as the load varies and only a
+    /// burst of allocations collects in between.
+    pub fn set_adaptive_threshold(&mut self, enabled: bool) {
+        self.adaptive_threshold = enabled;
+    }
+
+    /// Call `shrink_to_fit` after every collection that frees more than
+    /// `percent` of the collected objects; 0 turns this off
+    pub fn set_auto_shrink(&mut self, percent: usize) {
+        self.shrink_percent = percent;
+    }
+
+    /// Release the spare capacity of the heap's internal buffers
+    ///
+    /// Also hands the cached free slots back to the allocator. Returns the
+    /// number of bytes released.
+    pub fn shrink_to_fit(&mut self) -> usize {
+        fn shrink<T>(vec: &mut Vec<T>) -> usize {
+            let before = vec.capacity();
+            vec.shrink_to_fit();
+            (before - vec.capacity()) * mem::size_of::<T>()
+        }
+
+        let mut released = 0;
+        for slot in self.free_list.drain(..) {
+            released += slot.layout.size();
+            unsafe { dealloc(slot.ptr.as_ptr(), slot.layout) };
+        }
+        if let Some((region, layout)) = self.bump_region.take() {
+            released += layout.size();
+            unsafe { dealloc(region.as_ptr(), layout) };
+        }
+        // Bits past the last object are all clear
+        let words = self.objects.len().div_ceil(BITS_PER_WORD);
+        self.tracer.marks.truncate(words);
+
+        released += shrink(&mut self.objects);
+        self.purge_roots();
+        released += shrink(&mut self.roots);
+        released += shrink(&mut self.root_counts);
+        released += shrink(&mut self.pinned);
+        released += shrink(&mut self.remembered);
+        released += shrink(&mut self.free_list);
+        released += shrink(&mut self.handles);
+        released += shrink(&mut self.weaks);
+        released += shrink(&mut self.doomed);
+        released += shrink(&mut self.tracer.marks);
+        released += shrink(&mut self.tracer.stack);
+        released += shrink(&mut self.tracer.pending);
+        released
+    }
+
+    /// Split the heap into a young and an old generation
+    ///
+    /// Collections then only trace and sweep young objects (minor
+    /// collections) until the old generation reaches `major_threshold`
+    /// objects. Young objects are promoted after surviving `promotion_age`
+    /// minor collections, and every survivor of a major collection is
+    /// promoted. A `promotion_age` of 0 turns generations off again.
+    ///
+    /// Storing a `Gc` into an existing object must then be followed by
+    /// `write_barrier` on that object, or a minor collection may free the
+    /// stored object while it is still referenced.
+    pub fn set_generations(&mut self, promotion_age: u8, major_threshold: usize) {
+        self.major_threshold = major_threshold;
+        self.min_major_threshold = major_threshold;
+        self.set_promote_threshold(promotion_age);
+    }
+
+    /// Change only the promotion age of `set_generations`: the number of
+    /// minor collections a young object must survive to be promoted
+    ///
+    /// Each object counts its survivals in its `GcBox`. 0 turns generations
+    /// off.
+    pub fn set_promote_threshold(&mut self, survivals: u8) {
+        self.promotion_age = survivals;
+        if survivals == 0 {
+            self.old_count = 0;
+            self.remembered.clear();
+        }
+    }
+
+    /// Record that a `Gc` was just stored into `owner`
+    ///
+    /// With generations, `owner` may now hold pointers to young objects, and
+    /// minor collections only follow edges out of the old generation for
+    /// remembered objects. During an incremental collection, an `owner`
+    /// that was already marked, including one allocated since the
+    /// collection started, is shaded gray again, so the collector rescans it
+    /// and cannot free a white object only it now refers to.
+    /// `GcCell::borrow_mut` calls it for the object holding the cell.
+    pub fn write_barrier(&mut self, owner: NonNull<GcBox<dyn Trace>>) {
+        let slot = unsafe { owner.as_ref().slot.get() };
+        if self.collecting && self.tracer.is_marked(slot) {
+            self.tracer.stack.push(owner);
+        }
+        if self.promotion_age == 0 {
+            return;
+        }
+        let remembered = self
+            .remembered
+            .iter()
+            .any(|obj| ptr::addr_eq(obj.as_ptr(), owner.as_ptr()));
+        if slot < self.old_count && !remembered {
+            self.remembered.push(owner);
+        }
+    }
+
+    /// Called before marking starts, e.g. to toggle an LED or sample a timer
+    pub fn set_on_collect_start(&mut self, hook: fn()) {
+        self.on_collect_start = Some(hook);
+    }
+
+    /// Called after sweeping with the stats of the finished collection
+    pub fn set_on_collect_end(&mut self, hook: fn(&CollectionStats)) {
+        self.on_collect_end = Some(hook);
+    }
+
+    /// Called after every collection with a `Weak` to each tracked object
+    ///
+    /// The observer may keep the `Weak`s, e.g. to notice which objects a
+    /// later collection frees; unlike `Gc`s stashed by a hook they are not
+    /// roots, so they keep nothing alive.
+    pub fn set_weak_observer(&mut self, observer: fn(Vec<Weak<dyn Trace>>)) {
+        self.weak_observer = Some(observer);
+    }
+
+    /// Send the heap's diagnostics to `logger`
+    ///
+    /// Defaults to `SemihostingLogger` with the `semihosting-log` feature and
+    /// to `NoopLogger` otherwise.
+    pub fn set_logger(&mut self, logger: &'static dyn GcLogger) {
+        self.logger = logger;
+        self.tracer.logger = logger;
+    }
+
+    pub fn allocate<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        let gc = self.allocate_box(value);
+        if self.collection_due(0) {
+            // The caller has had no chance to root the new object yet, so
+            // treat it as a root for this collection
+            self.auto_collect(&[gc.as_non_null()]);
+        }
+        gc
+    }
+
+    /// Allocate a value built by `f`, which gets the object's own handle
+    ///
+    /// Lets a value point at itself, e.g. a node whose `child` is the node.
+    /// The object is only tracked once written, so nothing traces it before.
+    ///
+    /// # Safety
+    ///
+    /// The handle refers to uninitialized memory until `allocate_with`
+    /// returns, so `f` may store it but must not dereference it, nor hand it
+    /// to anything that does.
+    pub unsafe fn allocate_with<T, F>(&mut self, f: F) -> Gc<T>
+    where
+        T: Trace + 'static,
+        F: FnOnce(Gc<dyn Trace>) -> T,
+    {
+        let layout = Layout::new::<GcBox<T>>();
+        let ptr = match NonNull::new(self.alloc_slot(layout) as *mut GcBox<T>) {
+            Some(ptr) => ptr,
+            None => handle_alloc_error(layout),
+        };
+        let reservation = Reservation {
+            ptr: ptr.cast(),
+            layout,
+        };
+        let value = f(Gc::<dyn Trace> { ptr });
+        mem::forget(reservation);
+
+        unsafe { ptr.as_ptr().write(GcBox::new(value)) };
+        self.track(ptr);
+        let gc = Gc { ptr };
+        if self.collection_due(0) {
+            self.auto_collect(&[gc.as_non_null()]);
+        }
+        gc
+    }
+
+    /// Allocate every value before checking the threshold once
+    ///
+    /// A collection triggered by the batch keeps all of its objects, so the
+    /// earlier ones need no rooting while the rest are allocated.
+    pub fn allocate_many<T, I>(&mut self, values: I) -> Vec<Gc<dyn Trace>>
+    where
+        T: Trace + 'static,
+        I: IntoIterator<Item = T>,
+    {
+        let batch: Vec<Gc<dyn Trace>> = values
+            .into_iter()
+            .map(|value| self.allocate_box(value).unsize())
+            .collect();
+        if self.collection_due(0) {
+            let extra: Vec<_> = batch.iter().map(|gc| gc.as_non_null()).collect();
+            self.auto_collect(&extra);
+        }
+        batch
+    }
+
+    /// Allocate and track a box without considering a collection
+    fn allocate_box<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        let layout = Layout::new::<GcBox<T>>();
+        let ptr = match NonNull::new(self.alloc_slot(layout) as *mut GcBox<T>) {
+            Some(ptr) => ptr,
+            None => handle_alloc_error(layout),
+        };
+        unsafe { ptr.as_ptr().write(GcBox::new(value)) };
+        // Erase while the concrete type is still known
+        self.track(ptr);
+        unsafe { Gc::from_raw(ptr) }
+    }
+
+    /// Allocate `values` as a slice
+    ///
+    /// The box is tracked as the `[T; N]` it was built from, so the slice
+    /// elements are traced, finalized and dropped one by one. Root it with
+    /// `register_root(heap.object_of(&gc))`.
+    pub fn allocate_slice<T: Trace + 'static, const N: usize>(
+        &mut self,
+        values: [T; N],
+    ) -> Gc<[T]> {
+        let array = self.allocate(values);
+        let ptr: NonNull<GcBox<[T]>> = array.ptr;
+        Gc { ptr }
+    }
+
+    /// Allocate a slice of clones of `values`, whose length need not be
+    /// known at compile time
+    ///
+    /// The box is laid out like a `GcBox<[T]>`, behind a word holding the
+    /// length for the heap's view of it as a `SliceElems<T>`. Root it with
+    /// `register_root(heap.object_of(&gc))`.
+    pub fn allocate_slice_from<T: Trace + Clone + 'static>(&mut self, values: &[T]) -> Gc<[T]> {
+        let (layout, offset) = SliceElems::<T>::block_layout(values.len());
+        let block = match NonNull::new(self.alloc_slot(layout)) {
+            Some(block) => block,
+            None => handle_alloc_error(layout),
+        };
+        let start = unsafe { block.as_ptr().add(offset) };
+        let erased = start as *mut GcBox<SliceElems<T>>;
+        unsafe {
+            (start as *mut usize).sub(1).write(values.len());
+            // Before the elements, as the header's padding may overlap them
+            erased.write(GcBox::with_type_id(
+                SliceElems([]),
+                TypeId::of::<SliceElems<T>>(),
+            ));
+        }
+
+        // Clones made before one panics are leaked, not dropped
+        let reservation = Reservation { ptr: block, layout };
+        let elems = start.wrapping_add(SliceElems::<T>::VALUE_OFFSET) as *mut T;
+        for (index, value) in values.iter().enumerate() {
+            unsafe { elems.add(index).write(value.clone()) };
+        }
+        mem::forget(reservation);
+        self.track(unsafe { NonNull::new_unchecked(erased) });
+        let ptr = ptr::slice_from_raw_parts_mut(start as *mut T, values.len()) as *mut GcBox<[T]>;
+        let gc = Gc {
+            ptr: unsafe { NonNull::new_unchecked(ptr) },
+        };
+        if self.collection_due(0) {
+            self.auto_collect(&[self.object_of(&gc)]);
+        }
+        gc
+    }
+
+    /// Run `f` with a bump allocator over a region of `capacity` bytes
+    ///
+    /// For scratch data that never escapes `f`: each `Bump::alloc` just
+    /// advances an offset, and the whole region is reclaimed at once when `f`
+    /// returns (or unwinds). Only values that need dropping are remembered
+    /// and dropped then, newest first. Nothing in the region is traced, so a
+    /// `Gc` stored there does not keep its target alive. The region is kept
+    /// for the next scope until `shrink_to_fit`.
+    pub fn bump_scope<R>(&mut self, capacity: usize, f: impl FnOnce(&Bump) -> R) -> R {
+        let (region, layout) = match self.bump_region.take() {
+            Some((region, layout)) if layout.size() >= capacity => (region, layout),
+            old => {
+                if let Some((region, layout)) = old {
+                    unsafe { dealloc(region.as_ptr(), layout) };
+                }
+                let layout = match Layout::from_size_align(capacity.max(1), BUMP_ALIGN) {
+                    Ok(layout) => layout,
+                    Err(_) => handle_alloc_error(Layout::new::<u8>()),
+                };
+                match NonNull::new(unsafe { alloc::alloc::alloc(layout) }) {
+                    Some(region) => (region, layout),
+                    None => handle_alloc_error(layout),
+                }
+            }
+        };
+
+        // Kept by the heap right away, so unwinding out of `f` leaks nothing
+        self.bump_region = Some((region, layout));
+        let bump = Bump {
+            start: region,
+            capacity: layout.size(),
+            used: Cell::new(0),
+            drops: RefCell::new(Vec::new()),
+        };
+        f(&bump)
+    }
+
+    /// Allocate `value` and pin it right away, see `pin_root`
+    pub fn allocate_pinned<T: Trace + 'static>(&mut self, value: T) -> Gc<T> {
+        let gc = self.allocate(value);
+        self.pin_root(gc);
+        gc
+    }
+
+    /// Allocate an object that `compact` is allowed to move
+    pub fn allocate_handle<T: Trace + 'static>(&mut self, value: T) -> GcHandle<T> {
+        let gc = self.allocate(value);
+        let slot = Box::new(HandleSlot {
+            ptr: Cell::new(gc.as_non_null()),
+            erase: erase_at::<T>,
+        });
+        let slot = unsafe { NonNull::new_unchecked(Box::into_raw(slot)) };
+        self.handles.push(slot);
+        GcHandle {
+            slot,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like `allocate`, but reports an exhausted allocator instead of aborting
+    ///
+    /// Collects first when the threshold is about to be reached, and once
+    /// more before giving up if the allocator is out of memory.
+    pub fn try_allocate<T: Trace + 'static>(&mut self, value: T) -> Result<Gc<T>, AllocError> {
+        if self.collection_due(1) {
+            self.auto_collect(&[]);
+        }
+
+        // `track` must not abort on a full `objects` vector or bitmap either
+        self.objects.try_reserve(1).map_err(|_| AllocError)?;
+        self.tracer.marks.try_reserve(1).map_err(|_| AllocError)?;
+
+        let layout = Layout::new::<GcBox<T>>();
+        let mut raw = self.alloc_slot(layout) as *mut GcBox<T>;
+        if raw.is_null() {
+            self.auto_collect(&[]);
+            raw = self.alloc_slot(layout) as *mut GcBox<T>;
+        }
+        let ptr = NonNull::new(raw).ok_or(AllocError)?;
+        unsafe { ptr.as_ptr().write(GcBox::new(value)) };
+        self.track(ptr);
+
+        Ok(unsafe { Gc::from_raw(ptr) })
+    }
+
+    /// Uninitialized storage for `layout`, preferring a cached slot; null on OOM
+    ///
+    /// A cached slot is only reused for the identical layout, alignment
+    /// included, so an over-aligned type never lands in a slot freed by a
+    /// less aligned one of the same size.
+    fn alloc_slot(&mut self, layout: Layout) -> *mut u8 {
+        if let Some(index) = self.free_list.iter().position(|slot| slot.layout == layout) {
+            self.reused_slots += 1;
+            return self.free_list.swap_remove(index).ptr.as_ptr();
+        }
+        unsafe { alloc::alloc::alloc(layout) }
+    }
+
+    /// Number of allocations that reused the memory of a swept object
+    pub fn reused_slots(&self) -> usize {
+        self.reused_slots
+    }
+
+    /// Start tracking a freshly allocated box
+    fn track(&mut self, ptr: NonNull<GcBox<dyn Trace>>) {
+        let slot = self.objects.len();
+        unsafe { ptr.as_ref().slot.set(slot) };
+        #[cfg(debug_assertions)]
+        unsafe {
+            ptr.as_ref().heap_id.set(self.id())
+        };
+        self.tracer.reserve_slot(slot);
+        if self.collecting {
+            // Allocate gray: the object survives the cycle in progress, and
+            // is scanned before the sweep so whatever it was built with or
+            // is given in the meantime survives too
+            self.tracer.set_mark(slot);
+            self.tracer.stack.push(ptr);
+        } else if self.sweep.is_some() {
+            // Marking is over, so black is enough to survive the sweep
+            self.tracer.set_mark(slot);
+        }
+        self.objects.push(ptr);
+        self.allocation_count += 1;
+        self.allocations_since_collection += 1;
+    }
+
+    /// Threshold-triggered collection over the registered roots
+    fn auto_collect(&mut self, extra_roots: &[NonNull<GcBox<dyn Trace>>]) {
+        let mut roots = self.take_roots();
+        roots.extend_from_slice(extra_roots);
+        self.collect_garbage(&roots);
+        self.restore_roots(roots);
+        self.allocation_count = 0;
+    }
+
+    /// Copy the registered roots, followed by any found on the stack
+    ///
+    /// The registered set itself stays in place, so a collection unwinding
+    /// out of a panicking `Drop` impl cannot lose it. The copy reuses the
+    /// buffer handed back to `restore_roots`.
+    fn take_roots(&mut self) -> Vec<NonNull<GcBox<dyn Trace>>> {
+        self.purge_roots();
+        let mut roots = mem::take(&mut self.root_scratch);
+        roots.clear();
+        roots.extend_from_slice(&self.roots);
+        self.scan_stack(&mut roots);
+        roots
+    }
+
+    /// Keep the buffer of `take_roots` for the next collection
+    fn restore_roots(&mut self, roots: Vec<NonNull<GcBox<dyn Trace>>>) {
+        self.root_scratch = roots;
+    }
+
+    /// Treat anything on the stack that looks like a pointer into an object
+    /// as a root, in addition to the registered ones
+    ///
+    /// `bounds` returns the two ends of the stack in either order, see
+    /// `cortex_m_stack`. Every aligned word in between is compared against
+    /// the address range of each object, so interior pointers (e.g. a `&T`
+    /// obtained through `Deref`) count too. Candidates are never
+    /// dereferenced, so stray integers and misaligned values can at worst
+    /// keep an object alive. `None` turns the scan off again.
+    ///
+    /// # Safety
+    ///
+    /// Whenever the heap collects, all of the memory between the two ends
+    /// that `bounds` returns must be readable, as it is for the live part of
+    /// the current stack.
+    pub unsafe fn set_conservative_roots(
+        &mut self,
+        bounds: Option<fn() -> (*const u8, *const u8)>,
+    ) {
+        self.stack_bounds = bounds;
+    }
+
+    /// Push every object the stack may point at onto `found`
+    fn scan_stack(&self, found: &mut Vec<NonNull<GcBox<dyn Trace>>>) {
+        let bounds = match self.stack_bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        // Pointers held only in callee-saved registers are not on the
+        // stack yet
+        #[cfg(target_arch = "arm")]
+        {
+            let regs = callee_saved_registers();
+            let range = regs.as_ptr_range();
+            unsafe { self.scan_region(range.start as *const u8, range.end as *const u8, found) };
+        }
+
+        // The stack grows down on Cortex-M, but accept either order
+        let (a, b) = bounds();
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        // Readable, as promised to `set_conservative_roots`
+        unsafe { self.scan_region(low, high, found) };
+    }
+
+    /// # Safety
+    ///
+    /// `low..high` must be readable memory.
+    unsafe fn scan_region(
+        &self,
+        low: *const u8,
+        high: *const u8,
+        found: &mut Vec<NonNull<GcBox<dyn Trace>>>,
+    ) {
+        if self.objects.is_empty() {
+            return;
+        }
+        let word = mem::size_of::<usize>();
+        let mut addr = low.wrapping_add(low.align_offset(mem::align_of::<usize>()));
+        while (high as usize).saturating_sub(addr as usize) >= word {
+            let candidate = ptr::read_volatile(addr as *const usize);
+            if let Some(obj) = self.object_containing(candidate) {
+                found.push(obj);
+            }
+            addr = addr.add(word);
+        }
+    }
+
+    /// The object whose `GcBox` spans `addr`, if any
+    fn object_containing(&self, addr: usize) -> Option<NonNull<GcBox<dyn Trace>>> {
+        self.objects.iter().copied().find(|obj| {
+            let start = obj.as_ptr() as *const u8 as usize;
+            let size = box_size(unsafe { obj.as_ref() });
+            addr >= start && addr - start < size
+        })
+    }
+
+    /// Number of objects currently tracked by the heap
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Log a summary of the heap and every object, e.g. to inspect it from a
+    /// debug session
+    ///
+    /// Only with the `semihosting-log` feature, which also makes the
+    /// semihosting console the default logger.
+    #[cfg(feature = "semihosting-log")]
+    pub fn dump(&self) {
+        log!(
+            self.logger,
+            "gc: {} objects, {} roots, threshold {}, {} allocations since last collection",
+            self.objects.len(),
+            self.root_count(),
+            self.threshold,
+            self.allocation_count
+        );
+        for (slot, obj) in self.objects.iter().enumerate() {
+            let obj = unsafe { obj.as_ref() };
+            let marked = self.tracer.is_marked(slot);
+            #[cfg(debug_assertions)]
+            log!(
+                self.logger,
+                "gc:   [{}] {:p} marked={} {}",
+                slot,
+                obj,
+                marked,
+                obj.value.type_name()
+            );
+            #[cfg(not(debug_assertions))]
+            log!(
+                self.logger,
+                "gc:   [{}] {:p} marked={} {} bytes",
+                slot,
+                obj,
+                marked,
+                box_size(obj)
+            );
+        }
+    }
+
+    /// Write the object graph to `out` in Graphviz DOT format
+    ///
+    /// Each object is a node labeled with its address and, in debug builds,
+    /// its type name (its size otherwise); registered and pinned roots are
+    /// drawn bold. Edges are whatever the `Trace` impls report, so a skipped
+    /// `Gc` field is missing here too. Meant to be captured from the device,
+    /// e.g. over RTT, and rendered on the host.
+    pub fn trace_graph_dot(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        struct Edges<'a> {
+            out: &'a mut dyn fmt::Write,
+            from: usize,
+            result: fmt::Result,
+        }
+
+        impl<'a> Tracer for Edges<'a> {
+            fn visit(&mut self, gc: Gc<dyn Trace>) {
+                self.visit_slot(unsafe { gc.ptr.as_ref().slot.get() });
+            }
+
+            fn visit_slot(&mut self, slot: usize) {
+                if self.result.is_ok() {
+                    self.result = writeln!(self.out, "    n{} -> n{};", self.from, slot);
+                }
+            }
+        }
+
+        writeln!(out, "digraph gc {{")?;
+        for (slot, &ptr) in self.objects.iter().enumerate() {
+            let obj = unsafe { ptr.as_ref() };
+            let addr = ptr.as_ptr() as *const u8 as usize;
+            let style = if self.is_rooted(Gc { ptr }) {
+                ", style=bold"
+            } else {
+                ""
+            };
+            #[cfg(debug_assertions)]
+            writeln!(
+                out,
+                "    n{} [label=\"{}\\n{:#x}\"{}];",
+                slot,
+                obj.value.type_name(),
+                addr,
+                style
+            )?;
+            #[cfg(not(debug_assertions))]
+            writeln!(
+                out,
+                "    n{} [label=\"{} bytes\\n{:#x}\"{}];",
+                slot,
+                box_size(obj),
+                addr,
+                style
+            )?;
+        }
+        for (slot, obj) in self.objects.iter().enumerate() {
+            let mut edges = Edges {
+                out: &mut *out,
+                from: slot,
+                result: Ok(()),
+            };
+            unsafe { obj.as_ref().value.trace(&mut edges) };
+            edges.result?;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Snapshot of the collection trigger and the heap's contents
+    pub fn config(&self) -> HeapConfig {
+        HeapConfig {
+            threshold: self.threshold,
+            allocation_count: self.allocation_count,
+            object_count: self.objects.len(),
+            root_count: self.root_count(),
+        }
+    }
+
+    /// Record the object and root counts, to check for leaks later with
+    /// `HeapSnapshot::assert_balanced`
+    pub fn snapshot(&self) -> HeapSnapshot {
+        HeapSnapshot {
+            object_count: self.objects.len(),
+            root_count: self.root_count(),
+        }
+    }
+
+    /// Total size of the tracked values, excluding `GcBox` headers
+    pub fn live_bytes(&self) -> usize {
+        self.objects
+            .iter()
+            .map(|obj| value_size(unsafe { obj.as_ref() }))
+            .sum()
+    }
+
+    /// Report the bytes in use in the backing allocator to
+    /// `fragmentation_ratio`
+    ///
+    /// The allocator is external to the heap, so this is left to the user,
+    /// e.g. `|| ALLOCATOR.lock().used()` for a `LockedHeap`.
+    pub fn set_allocator_stats(&mut self, used: fn() -> usize) {
+        self.allocator_used = Some(used);
+    }
+
+    /// Estimated fraction of the allocator's used bytes not held by objects
+    ///
+    /// Compares the size of every tracked box, headers included, against the
+    /// figure from `set_allocator_stats`: 0.0 when the objects account for all
+    /// of it, approaching 1.0 as cached free slots, the heap's own buffers and
+    /// any other allocations take over. A rising ratio over a long-lived heap
+    /// suggests a `shrink_to_fit` or `compact`. 0.0 without allocator stats.
+    pub fn fragmentation_ratio(&self) -> f32 {
+        let used = match self.allocator_used {
+            Some(used) => used(),
+            None => return 0.0,
+        };
+        if used == 0 {
+            return 0.0;
+        }
+        let boxed: usize = self
+            .objects
+            .iter()
+            .map(|obj| block_of(unsafe { obj.as_ref() }).1.size())
+            .sum();
+        1.0 - boxed.min(used) as f32 / used as f32
+    }
+
+    /// Every object currently tracked by the heap, in allocation order within
+    /// each generation (old first) unless `sweep_step` reordered them
+    pub fn iter_objects(&self) -> impl Iterator<Item = &dyn Trace> {
+        self.objects
+            .iter()
+            .map(|obj| unsafe { &obj.as_ref().value })
+    }
+
+    /// Call `f` on the value of every registered root, then of every pinned
+    /// one, e.g. to find out what keeps a subgraph alive
+    pub fn for_each_root(&self, mut f: impl FnMut(&dyn Trace)) {
+        for root in self.live_roots().chain(&self.pinned) {
+            f(unsafe { &root.as_ref().value });
+        }
+    }
+
+    /// Every tracked object whose concrete type is `T`
+    pub fn objects_of_type<T: Trace + 'static>(&self) -> impl Iterator<Item = &T> {
+        self.iter_objects()
+            .filter_map(|value| value.as_any().downcast_ref::<T>())
+    }
+
+    /// Number of distinct objects reachable from `root`, itself included
+    ///
+    /// Walks the graph with its own visited set, leaving mark bits and any
+    /// collection in progress untouched.
+    pub fn reachable_from(&self, root: Gc<dyn Trace>) -> usize {
+        struct Reach<'a> {
+            objects: &'a [NonNull<GcBox<dyn Trace>>],
+            visited: Vec<usize>,
+            stack: Vec<NonNull<GcBox<dyn Trace>>>,
+            count: usize,
+        }
+
+        impl<'a> Tracer for Reach<'a> {
+            fn visit(&mut self, gc: Gc<dyn Trace>) {
+                self.visit_slot(unsafe { gc.ptr.as_ref().slot.get() });
+            }
+
+            fn visit_slot(&mut self, slot: usize) {
+                let (word, bit) = (slot / BITS_PER_WORD, 1 << (slot % BITS_PER_WORD));
+                if self.visited[word] & bit == 0 {
+                    self.visited[word] |= bit;
+                    self.count += 1;
+                    self.stack.push(self.objects[slot]);
+                }
+            }
+        }
+
+        let mut reach = Reach {
+            objects: &self.objects,
+            visited: vec![0; self.objects.len() / BITS_PER_WORD + 1],
+            stack: Vec::new(),
+            count: 0,
+        };
+        reach.visit(root);
+        while let Some(obj) = reach.stack.pop() {
+            unsafe { obj.as_ref().value.trace(&mut reach) };
+        }
+        reach.count
+    }
+
+    /// Move the value of `gc` out of the heap if nothing else refers to it
+    ///
+    /// Succeeds when no tracked object, `gc` itself included, traces an edge
+    /// to it, no handle or pin holds it and it is registered as a root at
+    /// most once; that registration is dropped and any `Weak`s to it die. The
+    /// value is moved, so it is neither finalized nor dropped by the heap.
+    /// Returns `None` and leaves the object in place if it is shared or an
+    /// incremental collection is marking.
+    ///
+    /// Edges a `Trace` impl does not report are not found, and `gc` and any
+    /// copies of it dangle afterwards.
+    pub fn take<T: Trace + 'static>(&mut self, gc: Gc<T>) -> Option<T> {
+        struct Find {
+            target: usize,
+            found: bool,
+        }
+
+        impl Tracer for Find {
+            fn visit(&mut self, gc: Gc<dyn Trace>) {
+                self.visit_slot(unsafe { gc.ptr.as_ref().slot.get() });
+            }
+
+            fn visit_slot(&mut self, slot: usize) {
+                self.found |= slot == self.target;
+            }
+        }
+
+        self.finish_sweep();
+        if self.collecting {
+            return None;
+        }
+        let ptr = self.object_of(&gc);
+        let slot = unsafe { ptr.as_ref().slot.get() };
+
+        self.purge_roots();
+        let root = self.find_root(ptr).ok();
+        if let Some(index) = root {
+            if self.root_counts[index] > 1 {
+                return None;
+            }
+        }
+        let pinned = self
+            .pinned
+            .iter()
+            .any(|pin| ptr::addr_eq(pin.as_ptr(), ptr.as_ptr()));
+        let handled = self.handles.iter().any(|handle| unsafe {
+            ptr::addr_eq(handle.as_ref().ptr.get().as_ptr(), ptr.as_ptr())
+        });
+        if pinned || handled || unsafe { ptr.as_ref().strong.get() } > 0 {
+            return None;
+        }
+        let mut find = Find {
+            target: slot,
+            found: false,
+        };
+        for obj in &self.objects {
+            unsafe { obj.as_ref().value.trace(&mut find) };
+            if find.found {
+                return None;
+            }
+        }
+
+        if let Some(index) = root {
+            self.roots.remove(index);
+            self.root_counts.remove(index);
+        }
+        self.untrack(ptr, slot);
+        unsafe {
+            let layout = Layout::for_value(gc.ptr.as_ref());
+            let value = ptr::read(&gc.ptr.as_ref().value);
+            free_box(&mut self.free_list, gc.ptr.cast(), layout);
+            Some(value)
+        }
+    }
+
+    /// Stop tracking the unrooted object `ptr` at `slot` without dropping
+    /// it; any `Weak`s to it die
+    ///
+    /// Only valid outside of a collection, when every mark bit is clear.
+    fn untrack(&mut self, ptr: NonNull<GcBox<dyn Trace>>, slot: usize) {
+        self.remembered
+            .retain(|obj| !ptr::addr_eq(obj.as_ptr(), ptr.as_ptr()));
+        let target_slot = unsafe { NonNull::from(&ptr.as_ref().slot) };
+        self.weaks.retain(|&flag| unsafe {
+            let weak = flag.as_ref();
+            if weak.target_slot != target_slot {
+                return true;
+            }
+            weak.alive.set(false);
+            if weak.weak_count.get() == 0 {
+                drop(Box::from_raw(flag.as_ptr()));
+            }
+            false
+        });
+
+        // Slots after it only need renumbering, their mark bits are clear
+        self.objects.remove(slot);
+        for (index, obj) in self.objects.iter().enumerate().skip(slot) {
+            unsafe { obj.as_ref().slot.set(index) };
+        }
+        if slot < self.old_count {
+            self.old_count -= 1;
+        }
+    }
+
+    /// Untrack a `Counted` object whose last handle is gone, unless
+    /// something else still roots it or a collection is in progress; the
+    /// caller then drops and frees it
+    fn release_counted(&mut self, ptr: NonNull<GcBox<dyn Trace>>) -> bool {
+        self.purge_roots();
+        if self.collecting || self.sweep.is_some() || self.find_root(ptr).is_ok() {
+            return false;
+        }
+        let slot = unsafe { ptr.as_ref().slot.get() };
+        let tracked = self
+            .objects
+            .get(slot)
+            .is_some_and(|&obj| ptr::addr_eq(obj.as_ptr(), ptr.as_ptr()));
+        let pinned = self
+            .pinned
+            .iter()
+            .any(|pin| ptr::addr_eq(pin.as_ptr(), ptr.as_ptr()));
+        if !tracked || pinned {
+            return false;
+        }
+        self.untrack(ptr, slot);
+        true
+    }
+
+    /// Borrow the pointee of `gc` for the duration of `f`
+    ///
+    /// The heap stays borrowed meanwhile, so no allocation or collection can
+    /// run and the reference cannot dangle, unlike one obtained by `Deref`.
+    pub fn access<T: ?Sized, R>(&self, gc: &Gc<T>, f: impl FnOnce(&T) -> R) -> R {
+        debug_assert!(
+            self.is_tracked(gc.ptr.as_ptr()),
+            "gc: access to a swept object"
+        );
+        f(unsafe { &gc.ptr.as_ref().value })
+    }
+
+    /// The tracked object behind any `Gc` of this heap, type erased
+    ///
+    /// Needed to root pointers that cannot be unsized to `Gc<dyn Trace>`,
+    /// such as `Gc<[T]>`. `gc` must not have been swept; panics if this heap
+    /// does not hold it.
+    pub fn object_of<T: ?Sized>(&self, gc: &Gc<T>) -> NonNull<GcBox<dyn Trace>> {
+        let slot = unsafe { gc.ptr.as_ref().slot.get() };
+        match self.objects.get(slot) {
+            Some(&obj) if ptr::addr_eq(obj.as_ptr(), gc.ptr.as_ptr()) => obj,
+            _ => panic!("gc: pointer is not a live object of this heap"),
+        }
+    }
+
+    /// `id`, drawn from `NEXT_HEAP_ID` on first use
+    #[cfg(debug_assertions)]
+    fn id(&self) -> usize {
+        if self.id.get() == 0 {
+            self.id.set(NEXT_HEAP_ID.fetch_add(1, Ordering::Relaxed));
+        }
+        self.id.get()
+    }
+
+    /// Panics in debug builds if `ptr` was allocated by another heap
+    fn debug_assert_owned(&self, ptr: NonNull<GcBox<dyn Trace>>) {
+        #[cfg(debug_assertions)]
+        {
+            let heap_id = unsafe { ptr.as_ref().heap_id.get() };
+            assert_eq!(
+                heap_id,
+                self.id(),
+                "gc: object {:p} belongs to another heap",
+                ptr.as_ptr()
+            );
+        }
+        let _ = ptr;
+    }
+
+    /// Whether `ptr` is one of this heap's live objects
+    fn is_tracked<T: ?Sized>(&self, ptr: *const GcBox<T>) -> bool {
+        self.objects
+            .iter()
+            .any(|obj| core::ptr::addr_eq(obj.as_ptr(), ptr))
+    }
+
+    /// Register `ptr` as a root, counting repeated registrations
+    ///
+    /// It stays a root until `unregister_root` was called as often.
+    pub fn register_root(&mut self, ptr: NonNull<GcBox<dyn Trace>>) {
+        self.debug_assert_owned(ptr);
+        match self.find_root(ptr) {
+            Ok(index) => {
+                if self.root_counts[index] == 0 {
+                    self.root_tombstones -= 1;
+                }
+                self.root_counts[index] += 1;
+            }
+            Err(index) => {
+                self.roots.insert(index, ptr);
+                self.root_counts.insert(index, 1);
+            }
+        }
+    }
+
+    /// Drop one registration of `ptr`
+    ///
+    /// The last one leaves a tombstone in place instead of shifting the rest
+    /// of the root set; the next collection purges them all at once.
+    pub fn unregister_root(&mut self, ptr: NonNull<GcBox<dyn Trace>>) {
+        if let Ok(index) = self.find_root(ptr) {
+            if self.root_counts[index] > 0 {
+                self.root_counts[index] -= 1;
+                if self.root_counts[index] == 0 {
+                    self.root_tombstones += 1;
+                }
+            }
+        }
+    }
+
+    /// Drop one registration of each of `ptrs`, compacting the root set once
+    pub fn unregister_roots(&mut self, ptrs: &[NonNull<GcBox<dyn Trace>>]) {
+        for &ptr in ptrs {
+            self.unregister_root(ptr);
+        }
+        self.purge_roots();
+    }
+
+    /// Remove the tombstones of unregistered roots in a single pass
+    fn purge_roots(&mut self) {
+        if self.root_tombstones == 0 {
+            return;
+        }
+        let mut kept = 0;
+        for index in 0..self.roots.len() {
+            if self.root_counts[index] > 0 {
+                self.roots[kept] = self.roots[index];
+                self.root_counts[kept] = self.root_counts[index];
+                kept += 1;
+            }
+        }
+        self.roots.truncate(kept);
+        self.root_counts.truncate(kept);
+        self.root_tombstones = 0;
+    }
+
+    /// Registered roots, skipping tombstones
+    fn live_roots(&self) -> impl Iterator<Item = &NonNull<GcBox<dyn Trace>>> {
+        self.roots
+            .iter()
+            .zip(&self.root_counts)
+            .filter(|&(_, &count)| count > 0)
+            .map(|(root, _)| root)
+    }
+
+    /// Number of registered roots
+    fn root_count(&self) -> usize {
+        self.roots.len() - self.root_tombstones
+    }
+
+    /// Whether `gc` is currently a registered or pinned root
+    ///
+    /// Conservative stack roots are only found during a collection and do
+    /// not count.
+    pub fn is_rooted(&self, gc: Gc<dyn Trace>) -> bool {
+        let ptr = gc.as_non_null();
+        self.find_root(ptr)
+            .is_ok_and(|index| self.root_counts[index] > 0)
+            || self
+                .pinned
+                .iter()
+                .any(|p| ptr::addr_eq(p.as_ptr(), ptr.as_ptr()))
+    }
+
+    /// Binary search for `ptr` in the address-sorted root set
+    fn find_root(&self, ptr: NonNull<GcBox<dyn Trace>>) -> Result<usize, usize> {
+        let addr = ptr.as_ptr() as *const u8 as usize;
+        self.roots
+            .binary_search_by_key(&addr, |root| root.as_ptr() as *const u8 as usize)
+    }
+
+    /// Root `gc` for good, e.g. a configuration singleton
+    ///
+    /// Pinned objects are roots of every collection, including `mark` and
+    /// `collect_garbage` with an explicit root set, and `unregister_root`
+    /// does not affect them.
+    pub fn pin_root(&mut self, gc: impl Into<Gc<dyn Trace>>) {
+        let ptr = gc.into().as_non_null();
+        self.debug_assert_owned(ptr);
+        if !self
+            .pinned
+            .iter()
+            .any(|p| ptr::addr_eq(p.as_ptr(), ptr.as_ptr()))
+        {
+            self.pinned.push(ptr);
+        }
+    }
+
+    /// Undo `pin_root`
+    pub fn unpin_root(&mut self, gc: impl Into<Gc<dyn Trace>>) {
+        let ptr = gc.into().as_non_null();
+        self.pinned
+            .retain(|p| !ptr::addr_eq(p.as_ptr(), ptr.as_ptr()));
+    }
+
+    /// Run `f` with `gc` rooted, unrooting it afterwards even if `f` panics
+    ///
+    /// `f` gets the heap back for allocating and collecting, and a `Rooted`
+    /// that cannot outlive the call. An object that was already a root stays
+    /// one.
+    pub fn with_root<T, R, F>(&mut self, gc: Gc<T>, f: F) -> R
+    where
+        T: Trace + 'static,
+        F: FnOnce(&mut Heap, Rooted<'_, T>) -> R,
+    {
+        struct Unroot {
+            heap: *mut Heap,
+            ptr: NonNull<GcBox<dyn Trace>>,
+        }
+
+        impl Drop for Unroot {
+            fn drop(&mut self) {
+                // `f` and its borrow of the heap are gone by now
+                unsafe { (*self.heap).unregister_root(self.ptr) };
+            }
+        }
+
+        let ptr = gc.as_non_null();
+        self.register_root(ptr);
+        let heap: *mut Heap = self;
+        let _unroot = Unroot { heap, ptr };
+        let rooted = Rooted {
+            gc,
+            _scope: PhantomData,
+        };
+        f(unsafe { &mut *heap }, rooted)
+    }
+
+    /// Collect everything unreachable from the registered roots
+    pub fn collect(&mut self) -> CollectionStats {
+        let roots = self.take_roots();
+        let stats = self.collect_garbage(&roots);
+        self.restore_roots(roots);
+        stats
+    }
+
+    /// Reclaim garbage cycles, such as `a -> b -> a` with neither rooted
+    ///
+    /// Marking starts from the roots only, so unlike reference counting a
+    /// cycle needs no special handling: this is `collect`, named for callers
+    /// porting from `Rc`. Every object of a cycle is finalized and dropped
+    /// exactly once.
+    pub fn collect_cycles(&mut self) -> CollectionStats {
+        self.collect()
+    }
+
+    /// Like `collect`, additionally appending the type names of the objects
+    /// that survived and of those that were freed
+    ///
+    /// Only objects of the collected generation are listed, so a minor
+    /// collection leaves the old one out. Debug builds only.
+    #[cfg(debug_assertions)]
+    pub fn retain_stats(
+        &mut self,
+        survivors: &mut Vec<&'static str>,
+        freed: &mut Vec<&'static str>,
+    ) -> CollectionStats {
+        self.retain_log = Some(RetainLog {
+            survivors: mem::take(survivors),
+            freed: mem::take(freed),
+        });
+        let stats = self.collect();
+        if let Some(log) = self.retain_log.take() {
+            *survivors = log.survivors;
+            *freed = log.freed;
+        }
+        stats
+    }
+
+    /// Collect everything unreachable from `roots`, ignoring the registered ones
+    ///
+    /// With generations enabled this is a minor collection of the young
+    /// generation unless the old one has reached its threshold. In debug
+    /// builds a root that is not one of this heap's objects, because it was
+    /// freed or belongs to another heap, is logged and skipped: a freed box
+    /// cannot be asked which heap it came from.
+    pub fn collect_garbage(&mut self, roots: &[NonNull<GcBox<dyn Trace>>]) -> CollectionStats {
+        self.purge_roots();
+        #[cfg(debug_assertions)]
+        for &root in roots {
+            // A stale root may point at freed memory, so only look inside
+            // tracked ones; `mark_from` skips the rest
+            if self.is_tracked(root.as_ptr()) {
+                self.debug_assert_owned(root);
+            }
+        }
+        let minor = self.promotion_age > 0 && self.old_count < self.major_threshold;
+        self.run_collection(roots, minor)
+    }
+
+    /// Collect one generation over the registered roots
+    ///
+    /// `0` is a minor collection of the young generation: old objects are
+    /// left alone, and only the edges recorded by the write barrier lead out
+    /// of them. Any higher number collects the old generation, which as there
+    /// are only two means a major collection of both. Without generations
+    /// every object is young, so either is a major collection.
+    pub fn collect_generation(&mut self, gen: usize) -> CollectionStats {
+        let minor = gen == 0 && self.promotion_age > 0;
+        let roots = self.take_roots();
+        let stats = self.run_collection(&roots, minor);
+        self.restore_roots(roots);
+        stats
+    }
+
+    /// Free every object and forget every root, e.g. at a subsystem restart
+    ///
+    /// Objects are finalized and dropped as in a sweep, cycles included, and
+    /// every `Gc` into the heap dangles afterwards. The configuration is
+    /// kept. Returns the number of objects freed.
+    pub fn reset(&mut self) -> usize {
+        // Its garbage is already finalized
+        self.finish_sweep();
+        self.tracer.clear_stack();
+        self.collecting = false;
+
+        self.roots.clear();
+        self.root_counts.clear();
+        self.root_tombstones = 0;
+        self.pinned.clear();
+        self.remembered.clear();
+        self.old_count = 0;
+        self.allocation_count = 0;
+        self.allocations_since_collection = 0;
+        self.rate_filled = 0;
+        self.tracer.marks.fill(0);
+        self.free_all()
+    }
+
+    /// Copy every object reachable from `root` into new objects, returning
+    /// the copy of `root`
+    ///
+    /// Shared objects are copied once, so a diamond stays a diamond, and
+    /// cycles are preserved. Every object reached must implement
+    /// `TraceClone`, or this panics. Nothing is collected until the copy is
+    /// complete.
+    pub fn deep_clone(&mut self, root: Gc<dyn Trace>) -> Gc<dyn Trace> {
+        let copy = Cloner {
+            heap: self,
+            copies: BTreeMap::new(),
+        }
+        .clone_dyn(root);
+        if self.collection_due(0) {
+            self.auto_collect(&[copy.as_non_null()]);
+        }
+        copy
+    }
+
+    /// Collect over the registered roots if an allocation would, as decided
+    /// by the `CollectionMode`; returns whether a collection ran
+    ///
+    /// Allocating already collects at the threshold, so this catches up on
+    /// e.g. a threshold that `set_threshold` lowered below the allocations
+    /// made since the last collection. Never collects in `Manual` mode.
+    pub fn collect_if_needed(&mut self) -> bool {
+        if !self.collection_due(0) {
+            return false;
+        }
+        self.auto_collect(&[]);
+        true
+    }
+
+    /// Collect everything unreachable from the registered roots right away
+    ///
+    /// Always a major collection, regardless of the allocation threshold and
+    /// the generations; e.g. to shrink the heap before entering a low-power
+    /// sleep. Also restarts the count towards the next automatic collection.
+    pub fn force_full_collection(&mut self) -> CollectionStats {
+        let roots = self.take_roots();
+        let stats = self.run_collection(&roots, false);
+        self.restore_roots(roots);
+        self.allocation_count = 0;
+        stats
+    }
+
+    fn run_collection(
+        &mut self,
+        roots: &[NonNull<GcBox<dyn Trace>>],
+        minor: bool,
+    ) -> CollectionStats {
+        #[cfg(feature = "cycle-count")]
+        let start = (self.clock)();
+
+        self.mark_from(roots, minor);
+        let stats = self.sweep_generation(minor);
+        #[cfg(feature = "cycle-count")]
+        {
+            self.last_pause_cycles = (self.clock)().wrapping_sub(start);
+        }
+        stats
+    }
+
+    /// Mark phase only: mark everything reachable from `roots` and the pinned
+    /// roots, ignoring the registered ones
+    ///
+    /// Inspect the result with `is_marked`, then free the rest with `sweep`.
+    /// Objects allocated in between are treated as reachable.
+    pub fn mark(&mut self, roots: &[NonNull<GcBox<dyn Trace>>]) {
+        self.mark_from(roots, false);
+        self.collecting = true;
+    }
+
+    /// Sweep phase only: free everything `mark` left unmarked
+    ///
+    /// Without a preceding `mark` (or `start_collection`), marks from the
+    /// registered roots first.
+    pub fn sweep(&mut self) -> CollectionStats {
+        if let Some(stats) = self.finish_sweep() {
+            return stats;
+        }
+        if !self.collecting {
+            self.start_collection();
+        }
+        self.tracer.scan(&self.objects, usize::MAX);
+        self.collecting = false;
+        self.sweep_generation(false)
+    }
+
+    /// Whether the last mark phase reached `gc`, which must not have been
+    /// swept yet
+    pub fn is_marked<T: ?Sized>(&self, gc: &Gc<T>) -> bool {
+        let slot = unsafe { gc.ptr.as_ref().slot.get() };
+        slot < self.tracer.floor || self.tracer.is_marked(slot)
+    }
+
+    /// Check the heap's internal invariants, for debugging the collector
+    ///
+    /// Every registered or pinned root must be a tracked object, no object
+    /// may be tracked twice, each object's slot must match its index, and
+    /// outside of a collection (including a pending `sweep_step` sweep) no
+    /// mark bit may be set. Returns the first violation found.
+    pub fn validate(&self) -> Result<(), HeapError> {
+        let mut addrs: Vec<usize> = self
+            .objects
+            .iter()
+            .map(|obj| obj.as_ptr() as *const u8 as usize)
+            .collect();
+        addrs.sort_unstable();
+        if let Some(pair) = addrs.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(HeapError::DuplicateObject { addr: pair[0] });
+        }
+        for (index, obj) in self.objects.iter().enumerate() {
+            if unsafe { obj.as_ref().slot.get() } != index {
+                return Err(HeapError::WrongSlot { slot: index });
+            }
+        }
+        for root in self.live_roots().chain(&self.pinned) {
+            let addr = root.as_ptr() as *const u8 as usize;
+            if addrs.binary_search(&addr).is_err() {
+                return Err(HeapError::UntrackedRoot { addr });
+            }
+        }
+
+        if !self.collecting && self.sweep.is_none() {
+            let slots = self.tracer.marks.len() * BITS_PER_WORD;
+            if let Some(slot) = (0..slots).find(|&slot| self.tracer.is_marked(slot)) {
+                return Err(HeapError::StaleMark { slot });
+            }
+        }
+        Ok(())
+    }
+
+    /// Report objects that look referenced by a marked object but were not
+    /// marked themselves, most likely a `Gc` field its `Trace` impl skips
+    ///
+    /// Call between `mark` and `sweep`. Every marked object's bytes are
+    /// scanned for addresses inside other objects, as in a conservative stack
+    /// scan, so a stale integer can cause a false report. Each suspect is
+    /// logged with both type names; returns how many were found. Debug builds
+    /// only.
+    #[cfg(debug_assertions)]
+    pub fn verify_tracing(&self) -> usize {
+        let mut suspects = 0;
+        let mut found = Vec::new();
+        for &obj in &self.objects {
+            let holder = unsafe { obj.as_ref() };
+            let slot = holder.slot.get();
+            if slot >= self.tracer.floor && !self.tracer.is_marked(slot) {
+                continue;
+            }
+
+            let low = &holder.value as *const dyn Trace as *const u8;
+            let high = low.wrapping_add(value_size(holder));
+            found.clear();
+            unsafe { self.scan_region(low, high, &mut found) };
+            for &target in &found {
+                let target_ref = unsafe { target.as_ref() };
+                let target_slot = target_ref.slot.get();
+                if ptr::addr_eq(target.as_ptr(), obj.as_ptr())
+                    || target_slot < self.tracer.floor
+                    || self.tracer.is_marked(target_slot)
+                {
+                    continue;
+                }
+                log!(
+                    self.logger,
+                    "gc: {} at {:p} is referenced by {} but was not traced",
+                    target_ref.value.type_name(),
+                    target.as_ptr(),
+                    holder.value.type_name()
+                );
+                suspects += 1;
+            }
+        }
+        suspects
+    }
+
+    fn mark_from(&mut self, roots: &[NonNull<GcBox<dyn Trace>>], minor: bool) {
+        self.finish_sweep();
+        if let Some(hook) = self.on_collect_start {
+            hook();
+        }
+
+        // A full collection supersedes any incremental one in progress
+        self.tracer.clear_stack();
+        self.collecting = false;
+
+        let from = if minor { self.old_count } else { 0 };
+        self.tracer.floor = from;
+        self.tracer.clear_marks_from(from);
+
+        if minor {
+            // Old objects are not traced, except for the edges that the
+            // write barrier recorded
+            for obj in &self.remembered {
+                unsafe { obj.as_ref().value.trace(&mut self.tracer) };
+            }
+        }
+
+        for &root in roots {
+            #[cfg(debug_assertions)]
+            {
+                if !self.is_tracked(root.as_ptr()) {
+                    log!(self.logger, "gc: skipping stale root {:p}", root.as_ptr());
+                    continue;
+                }
+            }
+            self.tracer.mark(root);
+        }
+        for &root in &self.pinned {
+            self.tracer.mark(root);
+        }
+        self.mark_counted();
+        self.tracer.scan(&self.objects, usize::MAX);
+    }
+
+    /// Begin an incremental collection by shading the registered roots gray
+    ///
+    /// Drive it with `step` and complete it with `finish_collection`. Objects
+    /// allocated meanwhile are allocated gray: they survive the cycle, and so
+    /// does everything they point at by the time marking finishes.
+    pub fn start_collection(&mut self) {
+        self.finish_sweep();
+        if let Some(hook) = self.on_collect_start {
+            hook();
+        }
+
+        self.tracer.clear_marks_from(0);
+
+        // Incremental collections always cover both generations
+        self.tracer.floor = 0;
+        self.tracer.clear_stack();
+        self.collecting = true;
+        self.shade_roots();
+    }
+
+    /// Scan at most `max_objects` gray objects; returns `true` once none are left
+    pub fn step(&mut self, max_objects: usize) -> bool {
+        self.tracer.scan(&self.objects, max_objects)
+    }
+
+    /// Finish marking and sweep the white objects
+    ///
+    /// Runs a complete collection if none was started.
+    pub fn finish_collection(&mut self) -> CollectionStats {
+        if !self.collecting {
+            self.start_collection();
+        } else {
+            // Pick up roots registered since `start_collection`
+            self.shade_roots();
+        }
+
+        self.tracer.scan(&self.objects, usize::MAX);
+        self.collecting = false;
+        self.sweep_generation(false)
+    }
+
+    /// Advance the current collection, starting one if none is under way,
+    /// until `max_cycles` have passed on the cycle clock
+    ///
+    /// Returns `true` once the collection is complete, or `false` if it was
+    /// left to be resumed by the next call (or finished by any other
+    /// collection). The clock is checked every `BUDGET_CHUNK` objects marked
+    /// or swept, survivors included, so every call makes some progress and
+    /// may overrun the budget by one chunk; the switch from marking to
+    /// sweeping also finalizes all of the garbage at once, as `sweep_step`
+    /// does.
+    #[cfg(feature = "cycle-count")]
+    pub fn collect_with_budget(&mut self, max_cycles: u32) -> bool {
+        let start = (self.clock)();
+        let clock = self.clock;
+        let expired = || clock().wrapping_sub(start) > max_cycles;
+
+        if !self.collecting && self.sweep.is_none() {
+            self.start_collection();
+        }
+        while self.collecting {
+            if self.tracer.scan(&self.objects, BUDGET_CHUNK) {
+                // Pick up roots registered since `start_collection`
+                self.shade_roots();
+                self.advance_sweep(0, 0);
+                break;
+            }
+            if expired() {
+                return false;
+            }
+        }
+        while self.sweep.is_some() {
+            self.advance_sweep(usize::MAX, BUDGET_CHUNK);
+            if self.sweep.is_some() && expired() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Objects with outstanding `Counted` handles are roots
+    fn mark_counted(&mut self) {
+        for &obj in &self.objects[self.tracer.floor..] {
+            if unsafe { obj.as_ref().strong.get() } > 0 {
+                self.tracer.mark(obj);
+            }
+        }
+    }
+
+    fn shade_roots(&mut self) {
+        self.purge_roots();
+        for &root in self.roots.iter() {
+            #[cfg(debug_assertions)]
+            {
+                if !self.is_tracked(root.as_ptr()) {
+                    log!(self.logger, "gc: skipping stale root {:p}", root.as_ptr());
+                    continue;
+                }
+            }
+            self.tracer.mark(root);
+        }
+        for &root in &self.pinned {
+            self.tracer.mark(root);
+        }
+        self.mark_counted();
+
+        if self.stack_bounds.is_some() {
+            let mut found = Vec::new();
+            self.scan_stack(&mut found);
+            for obj in found {
+                self.tracer.mark(obj);
+            }
+        }
+    }
+
+    /// Free every unmarked object and reset survivors to unmarked
+    ///
+    /// A minor sweep leaves the old generation untouched.
+    fn sweep_generation(&mut self, minor: bool) -> CollectionStats {
+        let from = if minor { self.old_count } else { 0 };
+        self.prepare_sweep(from);
+
+        let tracer = &self.tracer;
+        let doomed = &mut self.doomed;
+        self.objects.retain(|&ptr| {
+            let obj = unsafe { ptr.as_ref() };
+            let slot = obj.slot.get();
+            if slot < from {
+                return true;
+            }
+            let keep = tracer.is_marked(slot);
+            if !keep {
+                let (block, layout) = block_of(obj);
+                doomed.push((ptr, block, layout));
+            }
+            keep
+        });
+        // Leave the bits cleared for the next cycle; survivors are renumbered
+        // by `end_sweep`
+        self.tracer.clear_marks_from(from);
+
+        let objects_freed = self.doomed.len();
+        let bytes_freed = self.doomed.iter().map(|(_, _, layout)| layout.size()).sum();
+        // The heap is consistent again before any `Drop` impl runs, in case
+        // one panics
+        let stats = self.end_sweep(minor, objects_freed, bytes_freed);
+        DropDoomed::new(&mut self.doomed, &mut self.free_list).run();
+        self.report_sweep(&stats, from);
+        stats
+    }
+
+    /// Sweep phase in bounded steps: free at most `budget` unmarked objects
+    ///
+    /// The first call finishes marking like `sweep` does and finalizes all of
+    /// the garbage at once; only the freeing is spread over the calls. Returns
+    /// the number of objects freed, so a call freeing fewer than `budget`
+    /// completed the sweep and reported it to the collect end hook. Survivors
+    /// may be reordered. Any other collection completes a pending sweep first.
+    pub fn sweep_step(&mut self, budget: usize) -> usize {
+        self.advance_sweep(budget, usize::MAX).0
+    }
+
+    /// Complete a sweep left pending by `sweep_step`
+    fn finish_sweep(&mut self) -> Option<CollectionStats> {
+        self.sweep.as_ref()?;
+        self.advance_sweep(usize::MAX, usize::MAX).1
+    }
+
+    /// Free up to `budget` objects, looking at no more than `max_visited`
+    fn advance_sweep(
+        &mut self,
+        budget: usize,
+        max_visited: usize,
+    ) -> (usize, Option<CollectionStats>) {
+        if self.sweep.is_none() {
+            if !self.collecting {
+                self.start_collection();
+            }
+            self.tracer.scan(&self.objects, usize::MAX);
+            self.collecting = false;
+            self.prepare_sweep(0);
+            self.sweep = Some(SweepState {
+                cursor: 0,
+                objects_freed: 0,
+                bytes_freed: 0,
+            });
+        }
+        // Left in place, so that a panicking `Drop` impl leaves the sweep
+        // resumable
+        let state = match &mut self.sweep {
+            Some(state) => state,
+            None => unreachable!(),
+        };
+
+        // Slots equal indices here, and a freed object's hole is filled with
+        // the last one, so nothing after the cursor is renumbered twice
+        let mut freed = 0;
+        let mut visited = 0;
+        while freed < budget && visited < max_visited && state.cursor < self.objects.len() {
+            visited += 1;
+            let slot = state.cursor;
+            if self.tracer.is_marked(slot) {
+                self.tracer.clear_mark(slot);
+                state.cursor += 1;
+                continue;
+            }
+
+            let ptr = self.objects.swap_remove(slot);
+            let last = self.objects.len();
+            if slot < last {
+                unsafe { self.objects[slot].as_ref().slot.set(slot) };
+                if self.tracer.is_marked(last) {
+                    self.tracer.set_mark(slot);
+                    self.tracer.clear_mark(last);
+                }
+            }
+            state.objects_freed += 1;
+            freed += 1;
+            state.bytes_freed += unsafe { release(&mut self.free_list, ptr) };
+        }
+
+        if state.cursor < self.objects.len() {
+            return (freed, None);
+        }
+        let (objects_freed, bytes_freed) = (state.objects_freed, state.bytes_freed);
+        self.sweep = None;
+        let stats = self.end_sweep(false, objects_freed, bytes_freed);
+        self.report_sweep(&stats, 0);
+        (freed, Some(stats))
+    }
+
+    /// Finalize the garbage from `from` on and drop the handles and weak
+    /// references pointing at it, before any of it is freed
+    fn prepare_sweep(&mut self, from: usize) {
+        for obj in &self.objects[from..] {
+            let obj = unsafe { obj.as_ref() };
+            let marked = self.tracer.is_marked(obj.slot.get());
+            #[cfg(debug_assertions)]
+            {
+                if let Some(log) = &mut self.retain_log {
+                    let list = if marked {
+                        &mut log.survivors
+                    } else {
+                        &mut log.freed
+                    };
+                    list.push(obj.value.type_name());
+                }
+            }
+            if !marked {
+                obj.value.finalize();
+            }
+        }
+        for &obj in &self.objects[from..] {
+            unsafe {
+                if !self.tracer.is_marked(obj.as_ref().slot.get()) {
+                    (*obj.as_ptr()).value.clear_refs();
+                }
+            }
+        }
+
+        let tracer = &mut self.tracer;
+        self.handles.retain(|&slot| unsafe {
+            let target = slot.as_ref().ptr.get();
+            let target_slot = target.as_ref().slot.get();
+            let keep = target_slot < from || tracer.is_marked(target_slot);
+            if !keep {
+                drop(Box::from_raw(slot.as_ptr()));
+            }
+            keep
+        });
+
+        self.weaks.retain(|&flag| unsafe {
+            let weak = flag.as_ref();
+            let slot = weak.target_slot.as_ref().get();
+            let alive = slot < from || tracer.is_marked(slot);
+            weak.alive.set(alive);
+            let listed = alive && weak.weak_count.get() > 0;
+            if !listed && weak.weak_count.get() == 0 {
+                drop(Box::from_raw(flag.as_ptr()));
+            }
+            listed
+        });
+    }
+
+    /// Generation and threshold bookkeeping once the garbage is untracked
+    fn end_sweep(
+        &mut self,
+        minor: bool,
+        objects_freed: usize,
+        bytes_freed: usize,
+    ) -> CollectionStats {
+        let from = if minor { self.old_count } else { 0 };
+
+        if self.promotion_age == 0 {
+            self.old_count = 0;
+        } else if !minor {
+            // Everything that survived a major collection is old, and no
+            // old object can point at a young one yet
+            self.old_count = self.objects.len();
+            self.remembered.clear();
+            if self.growth_factor > 0 {
+                self.major_threshold = self
+                    .old_count
+                    .saturating_mul(self.growth_factor)
+                    .max(self.min_major_threshold);
+            }
+        } else {
+            self.promote();
+        }
+
+        for (slot, obj) in self.objects.iter().enumerate().skip(from) {
+            unsafe { obj.as_ref().slot.set(slot) };
+        }
+
+        self.rate_samples[self.rate_next] = self.allocations_since_collection;
+        self.rate_next = (self.rate_next + 1) % RATE_SAMPLES;
+        self.rate_filled = (self.rate_filled + 1).min(RATE_SAMPLES);
+        self.allocations_since_collection = 0;
+
+        let survivors = self.objects.len();
+        if self.adaptive_threshold {
+            self.threshold = self.allocation_rate().max(self.min_threshold);
+        } else if self.growth_factor > 0 && !minor {
+            // Minor collections leave the threshold alone, or a growing old
+            // generation would make them ever rarer
+            self.threshold = survivors
+                .saturating_mul(self.growth_factor)
+                .max(self.min_threshold);
+        }
+
+        let stats = CollectionStats {
+            objects_freed,
+            objects_surviving: survivors,
+            bytes_freed,
+            minor,
+        };
+        self.collection_seq += 1;
+        if let Some(clock) = self.collection_clock {
+            self.last_collection_tick = Some(clock());
+        }
+        stats
+    }
+
+    /// Auto-shrink and run the end hook and weak observer once the garbage is
+    /// freed
+    fn report_sweep(&mut self, stats: &CollectionStats, from: usize) {
+        let collected = stats.objects_freed + stats.objects_surviving - from;
+        if self.shrink_percent > 0 && stats.objects_freed * 100 > collected * self.shrink_percent {
+            self.shrink_to_fit();
+        }
+        if let Some(hook) = self.on_collect_end {
+            hook(stats);
+        }
+        if let Some(observer) = self.weak_observer {
+            let mut survivors = Vec::with_capacity(self.objects.len());
+            for index in 0..self.objects.len() {
+                let ptr = self.objects[index];
+                survivors.push(Weak::new(self, Gc { ptr }));
+            }
+            observer(survivors);
+        }
+    }
+
+    /// Finalize, drop and free every object, and release the memory cached
+    /// for reuse and the handle and weak reference bookkeeping; returns the
+    /// number of objects freed
+    fn free_all(&mut self) -> usize {
+        for obj in &self.objects {
+            unsafe { obj.as_ref().value.finalize() };
+        }
+        for &obj in &self.objects {
+            unsafe { (*obj.as_ptr()).value.clear_refs() };
+        }
+        self.doomed.extend(self.objects.iter().map(|&obj| {
+            let (block, layout) = block_of(unsafe { obj.as_ref() });
+            (obj, block, layout)
+        }));
+        let freed = self.objects.len();
+        self.objects.clear();
+        DropDoomed::new(&mut self.doomed, &mut self.free_list).run();
+        for slot in self.free_list.drain(..) {
+            unsafe { dealloc(slot.ptr.as_ptr(), slot.layout) };
+        }
+        if let Some((region, layout)) = self.bump_region.take() {
+            unsafe { dealloc(region.as_ptr(), layout) };
+        }
+        for slot in self.handles.drain(..) {
+            drop(unsafe { Box::from_raw(slot.as_ptr()) });
+        }
+        for flag in self.weaks.drain(..) {
+            unsafe {
+                flag.as_ref().alive.set(false);
+                if flag.as_ref().weak_count.get() == 0 {
+                    drop(Box::from_raw(flag.as_ptr()));
+                }
+            }
+        }
+        freed
+    }
+
+    /// Age the young survivors of a minor collection and promote the oldest
+    ///
+    /// Promoted objects move right behind the old generation, keeping their
+    /// order; the tracer's empty stack holds the rest meanwhile.
+    fn promote(&mut self) {
+        let stack = &mut self.tracer.stack;
+        let mut old_end = self.old_count;
+        for index in self.old_count..self.objects.len() {
+            let ptr = self.objects[index];
+            let obj = unsafe { ptr.as_ref() };
+            obj.age.set(obj.age.get().saturating_add(1));
+            if obj.age.get() >= self.promotion_age {
+                self.objects[old_end] = ptr;
+                old_end += 1;
+                // It may point at young objects that stay behind
+                self.remembered.push(ptr);
+            } else {
+                stack.push(ptr);
+            }
+        }
+        self.objects.truncate(old_end);
+        self.objects.append(stack);
+        self.old_count = old_end;
+    }
+
+    /// Move handle-allocated objects into lower free blocks of the allocator
+    ///
+    /// Only objects allocated with `allocate_handle` move, and only if they
+    /// are not registered or pinned roots (root sets hold raw addresses) nor
+    /// the target of an outstanding `Weak`, e.g. one kept from
+    /// `set_weak_observer`, which holds the address as well. An
+    /// object moves when the global allocator offers a block below its
+    /// current address, which with a first-fit allocator fills earlier holes.
+    /// Returns the number of relocated objects; does nothing mid incremental
+    /// collection.
+    ///
+    /// References obtained through `Deref` before compacting dangle afterwards.
+    pub fn compact(&mut self) -> usize {
+        if self.collecting {
+            return 0;
+        }
+        self.finish_sweep();
+
+        // Hand cached slots back first so the allocator can coalesce them
+        for slot in self.free_list.drain(..) {
+            unsafe { dealloc(slot.ptr.as_ptr(), slot.layout) };
+        }
+
+        self.purge_roots();
+        let roots = &self.roots;
+        let pinned = &self.pinned;
+        let mut moved = 0;
+        for &slot in &self.handles {
+            let slot = unsafe { slot.as_ref() };
+            let old = slot.ptr.get();
+            if roots
+                .iter()
+                .chain(pinned)
+                .any(|root| ptr::addr_eq(root.as_ptr(), old.as_ptr()))
+            {
+                continue;
+            }
+            let target_slot = NonNull::from(unsafe { &old.as_ref().slot });
+            let weak = self.weaks.iter().any(|flag| {
+                let flag = unsafe { flag.as_ref() };
+                flag.target_slot == target_slot && flag.weak_count.get() > 0
+            });
+            if weak {
+                continue;
+            }
+
+            let layout = Layout::for_value(unsafe { old.as_ref() });
+            let new = unsafe { alloc::alloc::alloc(layout) };
+            if new.is_null() {
+                break;
+            }
+            if new as usize > old.as_ptr() as *mut u8 as usize {
+                unsafe { dealloc(new, layout) };
+                continue;
+            }
+
+            let new = unsafe {
+                ptr::copy_nonoverlapping(old.as_ptr() as *const u8, new, layout.size());
+                (slot.erase)(NonNull::new_unchecked(new))
+            };
+            if let Some(entry) = self
+                .objects
+                .iter_mut()
+                .find(|obj| ptr::addr_eq(obj.as_ptr(), old.as_ptr()))
+            {
+                *entry = new;
+            }
+            if let Some(entry) = self
+                .remembered
+                .iter_mut()
+                .find(|obj| ptr::addr_eq(obj.as_ptr(), old.as_ptr()))
+            {
+                *entry = new;
+            }
+            // Flags whose `Weak`s are all gone would read the old box
+            self.weaks.retain(|&flag| unsafe {
+                let listed = flag.as_ref().target_slot != target_slot;
+                if !listed {
+                    drop(Box::from_raw(flag.as_ptr()));
+                }
+                listed
+            });
+            unsafe { dealloc(old.as_ptr() as *mut u8, layout) };
+            slot.ptr.set(new);
+            moved += 1;
+        }
+        moved
+    }
+}
+
+/// Same as `Heap::new`
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+/// Frees every object still tracked, so dropping a heap does not leak
+///
+/// All remaining objects are finalized first, as in a sweep. Any `Gc` into
+/// the heap dangles afterwards.
+impl Drop for Heap {
+    fn drop(&mut self) {
+        // Its garbage is already finalized
+        self.finish_sweep();
+
+        #[cfg(debug_assertions)]
+        {
+            if !self.objects.is_empty() {
+                log!(
+                    self.logger,
+                    "gc: freeing {} objects still alive at heap teardown",
+                    self.objects.len()
+                );
+            }
+        }
+
+        self.free_all();
+    }
+}
+
+/// Current stack pointer and initial stack pointer, for
+/// `Heap::set_conservative_roots`
+///
+/// Reads the active stack pointer, so it also works on the process stack as
+/// long as that stack lies below `_stack_start`.
+#[cfg(target_arch = "arm")]
+pub fn cortex_m_stack() -> (*const u8, *const u8) {
+    extern "C" {
+        // Provided by the cortex-m-rt linker script
+        static _stack_start: u8;
+    }
+    let sp: *const u8;
+    unsafe {
+        core::arch::asm!("mov {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags));
+        (sp, ptr::addr_of!(_stack_start))
+    }
+}
+
+/// Snapshot of r4-r11, which may hold the only copy of a pointer
+#[cfg(target_arch = "arm")]
+fn callee_saved_registers() -> [usize; 8] {
+    let mut regs = [0usize; 8];
+    unsafe {
+        core::arch::asm!(
+            "stm {}, {{r4-r11}}",
+            in(reg) regs.as_mut_ptr(),
+            options(nostack, preserves_flags),
+        );
+    }
+    regs
+}
+
+/// Drop and free a swept object; returns its size
+unsafe fn release(free_list: &mut Vec<FreeSlot>, ptr: NonNull<GcBox<dyn Trace>>) -> usize {
+    /// Frees the memory even if the `Drop` impl panics
+    struct Free<'a> {
+        free_list: &'a mut Vec<FreeSlot>,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    }
+
+    impl<'a> Drop for Free<'a> {
+        fn drop(&mut self) {
+            unsafe { free_box(self.free_list, self.ptr, self.layout) };
+        }
+    }
+
+    let (block, layout) = block_of(ptr.as_ref());
+    let _free = Free {
+        free_list,
+        ptr: block,
+        layout,
+    };
+    ptr::drop_in_place(ptr.as_ptr());
+    layout.size()
+}
+
+/// Start and layout of the memory block holding `obj`, usually just the box
+fn block_of(obj: &GcBox<dyn Trace>) -> (NonNull<u8>, Layout) {
+    match obj.value.allocation() {
+        Some(block) => block,
+        None => (NonNull::from(obj).cast(), Layout::for_value(obj)),
+    }
+}
+
+/// Bytes from the start of `obj` to the end of its block
+fn box_size(obj: &GcBox<dyn Trace>) -> usize {
+    let (start, layout) = block_of(obj);
+    start.as_ptr() as usize + layout.size() - (obj as *const GcBox<dyn Trace> as *const u8 as usize)
+}
+
+/// Bytes from the start of `obj`'s value to the end of its block, or the
+/// size of the value where its type describes the block
+fn value_size(obj: &GcBox<dyn Trace>) -> usize {
+    match obj.value.allocation() {
+        Some((start, layout)) => {
+            start.as_ptr() as usize + layout.size()
+                - (&obj.value as *const dyn Trace as *const u8 as usize)
+        }
+        None => size_of_val(&obj.value),
+    }
+}
+
+/// Free the memory of a dropped object, keeping it for reuse while the free
+/// list has room; returns its size
+unsafe fn free_box(free_list: &mut Vec<FreeSlot>, ptr: NonNull<u8>, layout: Layout) -> usize {
+    if free_list.len() < FREE_LIST_LIMIT {
+        free_list.push(FreeSlot { layout, ptr });
+    } else {
+        dealloc(ptr.as_ptr(), layout);
+    }
+    layout.size()
+}
+
+/// A swept object in `Heap::doomed`, with the block to free once dropped
+type Doomed = (NonNull<GcBox<dyn Trace>>, NonNull<u8>, Layout);
+
+/// Drops the objects in `Heap::doomed`, then frees their memory
+///
+/// If a `Drop` impl panics, the guard still drops the rest and frees all of
+/// them while unwinding, so nothing is dropped twice or leaked.
+struct DropDoomed<'a> {
+    doomed: &'a mut Vec<Doomed>,
+    free_list: &'a mut Vec<FreeSlot>,
+    dropped: usize,
+}
+
+impl<'a> DropDoomed<'a> {
+    fn new(doomed: &'a mut Vec<Doomed>, free_list: &'a mut Vec<FreeSlot>) -> Self {
+        DropDoomed {
+            doomed,
+            free_list,
+            dropped: 0,
+        }
+    }
+
+    /// Drop everything before freeing anything, so no `Drop` impl can read
+    /// freed memory
+    fn run(&mut self) {
+        while let Some(&(ptr, _, _)) = self.doomed.get(self.dropped) {
+            self.dropped += 1;
+            unsafe { ptr::drop_in_place(ptr.as_ptr()) };
+        }
+    }
+}
+
+impl<'a> Drop for DropDoomed<'a> {
+    fn drop(&mut self) {
+        // Only has objects left to drop when unwinding out of `run`
+        self.run();
+        for (_, block, layout) in self.doomed.drain(..) {
+            unsafe { free_box(self.free_list, block, layout) };
+        }
+    }
+}
+
+/// Memory for a box whose value is still being built, freed again if
+/// building it panics
+struct Reservation {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+/// A sweep spread over several `Heap::sweep_step` calls
+struct SweepState {
+    /// Objects below this index are swept survivors
+    cursor: usize,
+    objects_freed: usize,
+    bytes_freed: usize,
+}
+
+/// Caller's lists, borrowed by the heap during `Heap::retain_stats`
+#[cfg(debug_assertions)]
+struct RetainLog {
+    survivors: Vec<&'static str>,
+    freed: Vec<&'static str>,
+}
+
+/// When allocations trigger a collection, see `Heap::set_mode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionMode {
+    /// Collect on every allocation, e.g. to flush out rooting bugs
+    Eager,
+    /// Collect once this many allocations were made since the last
+    /// collection, subject to the growth factor
+    Threshold(usize),
+    /// Only collect when asked to
+    Manual,
+}
+
+/// Returned by `Heap::try_allocate` when the allocator is exhausted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+/// Invariant violation found by `Heap::validate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// A registered or pinned root at `addr` is not a tracked object
+    UntrackedRoot { addr: usize },
+    /// The object at `addr` is tracked more than once
+    DuplicateObject { addr: usize },
+    /// The object at index `slot` records a different slot
+    WrongSlot { slot: usize },
+    /// The mark bit of `slot` is set outside of a collection
+    StaleMark { slot: usize },
+}
+
+/// Returned by `Heap::config`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapConfig {
+    /// Allocations between automatic collections
+    pub threshold: usize,
+    /// Allocations since the last automatic collection
+    pub allocation_count: usize,
+    pub object_count: usize,
+    /// Registered roots
+    pub root_count: usize,
+}
+
+/// Object and root counts at a checkpoint, see `Heap::snapshot`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapSnapshot {
+    pub object_count: usize,
+    /// Registered roots
+    pub root_count: usize,
+}
+
+impl HeapSnapshot {
+    /// Whether `heap` holds no more objects and roots than at the snapshot
+    pub fn is_balanced(&self, heap: &Heap) -> bool {
+        let now = heap.snapshot();
+        now.object_count <= self.object_count && now.root_count <= self.root_count
+    }
+
+    /// Panic with the net growth unless `is_balanced`, e.g. at the end of a
+    /// test or of each pass of a request loop, after collecting
+    pub fn assert_balanced(&self, heap: &Heap) {
+        let now = heap.snapshot();
+        assert!(
+            self.is_balanced(heap),
+            "gc: heap grew by {} objects and {} roots since the snapshot",
+            now.object_count as isize - self.object_count as isize,
+            now.root_count as isize - self.root_count as isize
+        );
+    }
+}
+
+/// Result of a single collection
+#[derive(Debug, Clone)]
+pub struct CollectionStats {
+    pub objects_freed: usize,
+    pub objects_surviving: usize,
+    /// Size of the freed `GcBox`es, header included
+    pub bytes_freed: usize,
+    /// Only the young generation was collected
+    pub minor: bool,
+}
+
+/// A `Gc` rooted for the duration of a `Heap::with_root` call
+pub struct Rooted<'a, T: ?Sized> {
+    gc: Gc<T>,
+    _scope: PhantomData<&'a ()>,
+}
+
+impl<'a, T: ?Sized> Rooted<'a, T> {
+    /// The rooted pointer; only guaranteed to stay valid inside the scope
+    pub fn gc(&self) -> Gc<T> {
+        self.gc
+    }
+}
+
+impl<'a, T: ?Sized> Deref for Rooted<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.gc
+    }
+}
+
+/// A `Heap` for a `static`, lent out inside critical sections
+///
+/// `static GC: Mutex<RefCell<Heap>>` does not compile, as the cortex-m
+/// `Mutex` is only `Sync` for `Send` contents and a heap is not `Send`: its
+/// objects point at each other and at their `Cell` headers with plain
+/// pointers. A `GlobalHeap` makes the same promise as such a `Mutex` instead,
+/// with the caller vouching for the objects in `new`.
+pub struct GlobalHeap {
+    heap: RefCell<Heap>,
+}
+
+/// Only ever borrowed inside a critical section, see `GlobalHeap::new`
+unsafe impl Sync for GlobalHeap {}
+
+impl GlobalHeap {
+    /// An empty heap, e.g. `static GC: GlobalHeap = unsafe { GlobalHeap::new() };`
+    ///
+    /// # Safety
+    ///
+    /// Only for single-core targets, where a critical section keeps every
+    /// other execution context out. The objects' `Trace`, `Finalize` and
+    /// `Drop` impls must be fine to run from any context that borrows the
+    /// heap, and no `Gc` into it, or reference obtained through one, may be
+    /// used outside of a critical section, as a collection in an interrupt
+    /// handler could free its target.
+    pub const unsafe fn new() -> Self {
+        GlobalHeap {
+            heap: RefCell::new(Heap::new()),
+        }
+    }
+
+    pub fn borrow<'cs>(
+        &'cs self,
+        _cs: &'cs cortex_m::interrupt::CriticalSection,
+    ) -> &'cs RefCell<Heap> {
+        &self.heap
+    }
+}
+
+/// RAII root registration
+///
+/// The guard only borrows the heap while registering and unregistering, so
+/// the heap stays usable (allocation, collection) for the guard's lifetime.
+pub struct RootGuard<'a> {
+    heap: &'a RefCell<Heap>,
+    ptr: NonNull<GcBox<dyn Trace>>,
+}
+
+impl<'a> RootGuard<'a> {
+    pub fn new(heap: &'a RefCell<Heap>, gc: Gc<dyn Trace>) -> Self {
+        let ptr = gc.as_non_null();
+        heap.borrow_mut().register_root(ptr);
+        RootGuard { heap, ptr }
+    }
+
+    /// Keep the root registered for good, e.g. to promote the object to a
+    /// global, and return its pointer
+    ///
+    /// The registration outlives the guard's scope; only an explicit
+    /// `Heap::unregister_root` with the returned pointer drops it again.
+    pub fn leak(self) -> NonNull<GcBox<dyn Trace>> {
+        let ptr = self.ptr;
+        mem::forget(self);
+        ptr
+    }
+}
+
+impl<'a> Drop for RootGuard<'a> {
+    fn drop(&mut self) {
+        self.heap.borrow_mut().unregister_root(self.ptr);
+    }
+}
+
+/// Reference-counted handle for acyclic data, freed as soon as the last
+/// clone is dropped
+///
+/// Unlike `Gc` it is not `Copy`: each clone counts, and while any exist the
+/// object is a root. Dropping the last one finalizes, drops and frees the
+/// object right away instead of waiting for a collection, so it must not
+/// also be reachable through plain `Gc`s, which would dangle. Inside a
+/// collection, or while otherwise rooted, it is left to the next trace,
+/// which also catches the occasional cycle of `Counted` fields. All handles
+/// must be gone before `Heap::reset`.
+pub struct Counted<'a, T: Trace + 'static> {
+    heap: &'a RefCell<Heap>,
+    ptr: NonNull<GcBox<T>>,
+}
+
+impl<'a, T: Trace + 'static> Counted<'a, T> {
+    pub fn new(heap: &'a RefCell<Heap>, value: T) -> Self {
+        let gc = heap.borrow_mut().allocate(value);
+        unsafe { gc.ptr.as_ref().strong.set(1) };
+        Counted { heap, ptr: gc.ptr }
+    }
+
+    /// Number of handles to the object, this one included
+    pub fn strong_count(&self) -> usize {
+        unsafe { self.ptr.as_ref().strong.get() }
+    }
+
+    /// A plain pointer to the object, only valid while a handle lives
+    pub fn gc(&self) -> Gc<T> {
+        Gc { ptr: self.ptr }
+    }
+}
+
+impl<'a, T: Trace + 'static> Clone for Counted<'a, T> {
+    fn clone(&self) -> Self {
+        let strong = unsafe { &self.ptr.as_ref().strong };
+        strong.set(strong.get() + 1);
+        Counted {
+            heap: self.heap,
+            ptr: self.ptr,
+        }
+    }
+}
+
+impl<'a, T: Trace + 'static> Drop for Counted<'a, T> {
+    fn drop(&mut self) {
+        let strong = unsafe { &self.ptr.as_ref().strong };
+        strong.set(strong.get() - 1);
+        if strong.get() > 0 {
+            return;
+        }
+        // Busy when dropped by a sweep; the next collection frees it then
+        let released = match self.heap.try_borrow_mut() {
+            Ok(mut heap) => heap.release_counted(Gc { ptr: self.ptr }.as_non_null()),
+            Err(_) => false,
+        };
+        if released {
+            // Not borrowed meanwhile, so `Counted` fields can cascade
+            unsafe {
+                self.ptr.as_ref().value.finalize();
+                ptr::drop_in_place(self.ptr.as_ptr());
+            }
+            let layout = Layout::new::<GcBox<T>>();
+            match self.heap.try_borrow_mut() {
+                Ok(mut heap) => {
+                    unsafe { free_box(&mut heap.free_list, self.ptr.cast(), layout) };
+                }
+                Err(_) => unsafe { dealloc(self.ptr.as_ptr() as *mut u8, layout) },
+            }
+        }
+    }
+}
+
+impl<'a, T: Trace + 'static> Deref for Counted<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &self.ptr.as_ref().value }
+    }
+}
+
+impl<'a, T: Trace + 'static> Finalize for Counted<'a, T> {}
+
+/// Counts as an edge too, so a traced parent also keeps the object alive
+impl<T: Trace + 'static> Trace for Counted<'static, T> {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        tracer.visit(self.gc().unsize());
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Copies made so far by one `Heap::deep_clone`
+pub struct Cloner<'a> {
+    heap: &'a mut Heap,
+    /// Address of each original value and its copy
+    copies: BTreeMap<usize, NonNull<GcBox<dyn Trace>>>,
+}
+
+impl<'a> Cloner<'a> {
+    /// The copy of `gc`, made on first use
+    pub fn clone_gc<T: TraceClone>(&mut self, gc: Gc<T>) -> Gc<T> {
+        self.copy(&*gc)
+    }
+
+    /// Like `clone_gc`, for a `Gc<dyn Trace>`
+    pub fn clone_dyn(&mut self, gc: Gc<dyn Trace>) -> Gc<dyn Trace> {
+        let value: &dyn Trace = &*gc;
+        let addr = value as *const dyn Trace as *const u8 as usize;
+        if let Some(&ptr) = self.copies.get(&addr) {
+            return Gc { ptr };
+        }
+        match value.clone_object(self) {
+            Some(copy) => copy,
+            None => panic!(
+                "gc: deep_clone reached {:p}, which does not implement TraceClone",
+                gc.ptr.as_ptr()
+            ),
+        }
+    }
+
+    /// The copy of the object holding `value`, made on first use
+    ///
+    /// The new box is known to the cloner before `clone_with` runs, so a
+    /// cycle back to it yields a `Gc` that may be stored but not
+    /// dereferenced until the copy is complete.
+    pub fn copy<T: TraceClone>(&mut self, value: &T) -> Gc<T> {
+        let addr = value as *const T as *const u8 as usize;
+        if let Some(&ptr) = self.copies.get(&addr) {
+            return Gc { ptr: ptr.cast() };
+        }
+
+        let layout = Layout::new::<GcBox<T>>();
+        let ptr = match NonNull::new(self.heap.alloc_slot(layout) as *mut GcBox<T>) {
+            Some(ptr) => ptr,
+            None => handle_alloc_error(layout),
+        };
+        let reservation = Reservation {
+            ptr: ptr.cast(),
+            layout,
+        };
+        self.copies.insert(addr, ptr);
+        let copy = value.clone_with(self);
+        mem::forget(reservation);
+
+        unsafe { ptr.as_ptr().write(GcBox::new(copy)) };
+        self.heap.track(ptr);
+        Gc { ptr }
+    }
+}
+
+/// Alignment of `Heap::bump_scope` regions; stricter types are aligned by
+/// skipping bytes
+const BUMP_ALIGN: usize = 16;
+
+/// Drops the value at the given address in place
+type DropFn = unsafe fn(NonNull<u8>);
+
+/// Bump allocator lent out by `Heap::bump_scope`
+pub struct Bump {
+    start: NonNull<u8>,
+    capacity: usize,
+    /// Offset of the first free byte
+    used: Cell<usize>,
+    /// Values to drop when the scope ends, oldest first
+    drops: RefCell<Vec<(NonNull<u8>, DropFn)>>,
+}
+
+impl Bump {
+    /// Move `value` into the region, or fail if it has no room left
+    pub fn alloc<T>(&self, value: T) -> Result<&T, AllocError> {
+        unsafe fn drop_at<T>(ptr: NonNull<u8>) {
+            ptr::drop_in_place(ptr.cast::<T>().as_ptr());
+        }
+
+        let base = self.start.as_ptr() as usize;
+        let align = mem::align_of::<T>();
+        let offset = (base + self.used.get()).div_ceil(align) * align - base;
+        let end = offset.checked_add(mem::size_of::<T>()).ok_or(AllocError)?;
+        if end > self.capacity {
+            return Err(AllocError);
+        }
+        let ptr = unsafe { NonNull::new_unchecked(self.start.as_ptr().add(offset)) };
+        if mem::needs_drop::<T>() {
+            self.drops.borrow_mut().push((ptr, drop_at::<T>));
+        }
+        self.used.set(end);
+        let ptr = ptr.cast::<T>();
+        unsafe {
+            ptr.as_ptr().write(value);
+            Ok(&*ptr.as_ptr())
+        }
+    }
+
+    /// Bytes taken so far, alignment padding included
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+
+    /// Size of the region
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl Drop for Bump {
+    fn drop(&mut self) {
+        let drops = self.drops.get_mut();
+        while let Some((ptr, drop_at)) = drops.pop() {
+            unsafe { drop_at(ptr) };
+        }
+    }
+}
+
+/// A heap for objects that borrow from the enclosing scope
+///
+/// `allocate` accepts any `T: Trace + 'scope`, so objects may hold
+/// references into a stack frame. The arena cannot outlive `'scope` and
+/// frees everything it still tracks when dropped, so no object is traced,
+/// finalized or dropped after its borrows have expired. The underlying heap
+/// is only lent out immutably, because swapping it for another one would let
+/// the objects escape. A type that is not `'static` cannot return itself
+/// from `as_any`, and returning `&()` is fine; it reports its `Gc` fields
+/// with `Arena::visit`, as the `Trace` impls of `Gc` and the containers need
+/// `'static` types.
+pub struct Arena<'scope> {
+    heap: Heap,
+    /// Invariant, so the objects' lifetime can be neither shortened nor
+    /// extended
+    _scope: PhantomData<fn(&'scope ()) -> &'scope ()>,
+}
+
+impl<'scope> Arena<'scope> {
+    pub fn new() -> Self {
+        Arena {
+            heap: Heap::new(),
+            _scope: PhantomData,
+        }
+    }
+
+    pub fn with_threshold(threshold: usize) -> Self {
+        Arena {
+            heap: Heap::with_threshold(threshold),
+            _scope: PhantomData,
+        }
+    }
+
+    /// Like `Heap::allocate`, for values that may borrow from `'scope`
+    pub fn allocate<T: Trace + 'scope>(&mut self, value: T) -> Gc<T> {
+        let layout = Layout::new::<GcBox<T>>();
+        let ptr = match NonNull::new(self.heap.alloc_slot(layout) as *mut GcBox<T>) {
+            Some(ptr) => ptr,
+            None => handle_alloc_error(layout),
+        };
+        // Scoped types have no `TypeId`, and nothing downcasts to an `Arena`
+        let type_id = TypeId::of::<Arena<'static>>();
+        unsafe { ptr.as_ptr().write(GcBox::with_type_id(value, type_id)) };
+        let obj = Self::erase(ptr);
+        self.heap.track(obj);
+        if self.heap.collection_due(0) {
+            self.heap.auto_collect(&[obj]);
+        }
+        Gc { ptr }
+    }
+
+    pub fn register_root<T: Trace + 'scope>(&mut self, gc: &Gc<T>) {
+        self.heap.register_root(Self::erase(gc.ptr));
+    }
+
+    pub fn unregister_root<T: Trace + 'scope>(&mut self, gc: &Gc<T>) {
+        self.heap.unregister_root(Self::erase(gc.ptr));
+    }
+
+    /// Collect everything unreachable from the registered roots
+    pub fn collect(&mut self) -> CollectionStats {
+        self.heap.collect()
+    }
+
+    /// Report the edge to `gc`, from the `Trace` impl of a scoped object
+    pub fn visit<T: Trace + 'scope>(tracer: &mut dyn Tracer, gc: Gc<T>) {
+        tracer.visit(Gc {
+            ptr: Self::erase(gc.ptr),
+        });
+    }
+
+    /// View a scoped box as the `'static` trait object the heap stores
+    fn erase<T: Trace + 'scope>(ptr: NonNull<GcBox<T>>) -> NonNull<GcBox<dyn Trace>> {
+        let scoped: NonNull<GcBox<dyn Trace + 'scope>> = ptr;
+        // Only the lifetime changes; the arena frees the object before
+        // `'scope` ends
+        unsafe { mem::transmute(scoped) }
+    }
+}
+
+/// Frees the objects while `'scope` is still alive; having a `Drop` impl of
+/// its own makes the borrow checker insist on that, as the `Heap` inside is
+/// `'static` and would let the borrows expire first
+impl<'scope> Drop for Arena<'scope> {
+    fn drop(&mut self) {
+        self.heap.finish_sweep();
+        self.heap.free_all();
+    }
+}
+
+impl<'scope> Default for Arena<'scope> {
+    fn default() -> Self {
+        Arena::new()
+    }
+}
+
+impl<'scope> Deref for Arena<'scope> {
+    type Target = Heap;
+    fn deref(&self) -> &Heap {
+        &self.heap
+    }
+}
+
+/// RAII registration of a batch of roots
+///
+/// `push` collects objects and `finalize` registers everything pushed since
+/// the last call. Dropping the set unregisters the registered ones in a
+/// single pass over the root set.
+pub struct RootSet<'a> {
+    heap: &'a RefCell<Heap>,
+    ptrs: Vec<NonNull<GcBox<dyn Trace>>>,
+    /// Length of the prefix of `ptrs` that is registered
+    registered: usize,
+}
+
+impl<'a> RootSet<'a> {
+    pub fn new(heap: &'a RefCell<Heap>) -> Self {
+        RootSet {
+            heap,
+            ptrs: Vec::new(),
+            registered: 0,
+        }
+    }
+
+    /// Add `gc` to the batch; it is not a root before `finalize`
+    pub fn push(&mut self, gc: impl Into<Gc<dyn Trace>>) {
+        self.ptrs.push(gc.into().as_non_null());
+    }
+
+    /// Register the objects pushed since the last call
+    pub fn finalize(&mut self) {
+        let mut heap = self.heap.borrow_mut();
+        for &ptr in &self.ptrs[self.registered..] {
+            heap.register_root(ptr);
+        }
+        self.registered = self.ptrs.len();
+    }
+}
+
+impl<'a> Drop for RootSet<'a> {
+    fn drop(&mut self) {
+        self.heap
+            .borrow_mut()
+            .unregister_roots(&self.ptrs[..self.registered]);
+    }
+}
+
+/// Allocate a value and keep it rooted until the end of the enclosing block
+///
+/// `root!(heap, node = value);` with `heap: RefCell<Heap>` binds `node` to
+/// the new `Gc` and holds a hidden `RootGuard` for it, so the guard cannot be
+/// dropped early by accident.
+#[macro_export]
+macro_rules! root {
+    ($heap:expr, $name:ident = $value:expr) => {
+        // Evaluated first, so the value may itself borrow the heap
+        let value = $value;
+        let heap = &$heap;
+        let $name = heap.borrow_mut().allocate(value);
+        let _guard = $crate::gc::RootGuard::new(heap, $name.unsize());
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    //! Host-side tests, e.g. `cargo test --lib --target x86_64-unknown-linux-gnu`
+    //! since `.cargo/config` builds for the Cortex-M target by default
+
+    use super::*;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts its drops in a counter shared with the test
+    struct Canary {
+        drops: Rc<Cell<usize>>,
+        child: Option<Gc<dyn Trace>>,
+    }
+
+    impl Canary {
+        fn new(drops: &Rc<Cell<usize>>, child: Option<Gc<dyn Trace>>) -> Self {
+            Canary {
+                drops: drops.clone(),
+                child,
+            }
+        }
+    }
+
+    impl Drop for Canary {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    impl Finalize for Canary {}
+
+    impl Trace for Canary {
+        fn trace(&self, tracer: &mut dyn Tracer) {
+            self.child.trace(tracer);
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    /// Allocate `len` canaries, each pointing at the one before; returns the
+    /// last, the only one nothing points at
+    fn chain(heap: &mut Heap, drops: &Rc<Cell<usize>>, len: usize) -> Gc<dyn Trace> {
+        let mut last = heap.allocate(Canary::new(drops, None)).unsize();
+        for _ in 1..len {
+            last = heap.allocate(Canary::new(drops, Some(last))).unsize();
+        }
+        last
+    }
+
+    #[test]
+    fn allocate_keeps_the_new_object_alive() {
+        let drops = Rc::new(Cell::new(0));
+        // Collects on every allocation
+        let mut heap = Heap::new();
+        let first = heap.allocate(Canary::new(&drops, None));
+        assert_eq!(drops.get(), 0);
+
+        // Reachable from the object being allocated, and freed with it
+        heap.allocate(Canary::new(&drops, Some(first.unsize())));
+        assert_eq!(drops.get(), 0);
+        assert_eq!(heap.object_count(), 2);
+        heap.allocate(Canary::new(&drops, None));
+        assert_eq!(drops.get(), 2);
+    }
+
+    /// Stands in for the stack in the conservative scanning test
+    static FAKE_STACK: [AtomicUsize; 8] = [const { AtomicUsize::new(0) }; 8];
+
+    /// Both ends of `FAKE_STACK`, reversed as on a descending stack
+    fn fake_stack() -> (*const u8, *const u8) {
+        let range = FAKE_STACK.as_ptr_range();
+        (range.end as *const u8, range.start as *const u8)
+    }
+
+    #[test]
+    fn conservative_roots_from_a_simulated_stack() {
+        let mut heap = Heap::with_threshold(1000);
+        unsafe { heap.set_conservative_roots(Some(fake_stack)) };
+        let direct = heap.allocate(Canary::new(&Rc::new(Cell::new(0)), None));
+        let interior = heap.allocate(Canary::new(&Rc::new(Cell::new(0)), None));
+        heap.allocate(Canary::new(&Rc::new(Cell::new(0)), None));
+        FAKE_STACK[2].store(direct.as_ptr().as_ptr() as usize, Ordering::Relaxed);
+        FAKE_STACK[5].store(&interior.child as *const _ as usize, Ordering::Relaxed);
+        FAKE_STACK[6].store(0x1234_5677, Ordering::Relaxed);
+
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(heap.object_count(), 2);
+        FAKE_STACK[5].store(0, Ordering::Relaxed);
+        assert_eq!(heap.collect().objects_freed, 1);
+        FAKE_STACK[2].store(0, Ordering::Relaxed);
+        assert_eq!(heap.collect().objects_freed, 1);
+    }
+
+    #[test]
+    fn runtime_length_slice_survives_and_traces_its_elements() {
+        let mut heap = Heap::with_threshold(1000);
+        let values: Vec<u32> = (0..5).map(|i| i * 3).collect();
+        let numbers = heap.allocate_slice_from(&values);
+        heap.register_root(heap.object_of(&numbers));
+
+        let drops = Rc::new(Cell::new(0));
+        let children = [
+            heap.allocate(Canary::new(&drops, None)).unsize(),
+            heap.allocate(Canary::new(&drops, None)).unsize(),
+        ];
+        let parents = heap.allocate_slice_from(&children);
+        heap.register_root(heap.object_of(&parents));
+
+        assert_eq!(heap.collect().objects_freed, 0);
+        assert_eq!(&*numbers, &[0, 3, 6, 9, 12]);
+        assert_eq!(parents.len(), 2);
+        assert_eq!(
+            heap.live_bytes(),
+            5 * 4 + 2 * mem::size_of::<Gc<dyn Trace>>() + 2 * mem::size_of::<Canary>()
+        );
+
+        heap.unregister_root(heap.object_of(&parents));
+        let stats = heap.collect();
+        assert_eq!(stats.objects_freed, 3);
+        assert_eq!(drops.get(), 2);
+        assert_eq!(&*numbers, &[0, 3, 6, 9, 12]);
+        assert_eq!(heap.allocate_slice_from::<u32>(&[]).len(), 0);
+    }
+
+    #[test]
+    fn allocate_with_builds_a_self_referential_object() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        // Only stores the handle
+        let node = unsafe { heap.allocate_with(|this| Canary::new(&drops, Some(this))) };
+        assert!(ptr::addr_eq(
+            node.child.unwrap().as_ptr().as_ptr(),
+            node.as_ptr().as_ptr()
+        ));
+
+        heap.register_root(heap.object_of(&node));
+        assert_eq!(heap.collect().objects_freed, 0);
+        heap.unregister_root(heap.object_of(&node));
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn arena_frees_its_objects_when_dropped() {
+        let drops = Rc::new(Cell::new(0));
+        let mut arena = Arena::with_threshold(1000);
+        let kept = arena.allocate(Canary::new(&drops, None));
+        arena.register_root(&kept);
+        arena.allocate(Canary::new(&drops, None));
+        drop(arena);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn collect_if_needed_follows_the_mode() {
+        let mut heap = Heap::new();
+        heap.set_mode(CollectionMode::Threshold(4));
+        for _ in 0..3 {
+            heap.allocate(0u32);
+        }
+        assert!(!heap.collect_if_needed());
+        assert_eq!(heap.object_count(), 3);
+
+        heap.set_mode(CollectionMode::Manual);
+        for _ in 0..3 {
+            heap.allocate(0u32);
+        }
+        assert!(!heap.collect_if_needed());
+        assert_eq!(heap.object_count(), 6);
+
+        heap.set_mode(CollectionMode::Threshold(4));
+        assert!(heap.collect_if_needed());
+        assert_eq!(heap.object_count(), 0);
+    }
+
+    #[test]
+    fn allocate_collects_as_the_mode_says() {
+        let mut heap = Heap::new();
+        heap.set_mode(CollectionMode::Manual);
+        for n in 0..1000u32 {
+            heap.allocate(n);
+        }
+        assert_eq!(heap.collection_count(), 0);
+        assert_eq!(heap.object_count(), 1000);
+
+        let mut heap = Heap::new();
+        heap.set_mode(CollectionMode::Threshold(4));
+        for n in 0..3u32 {
+            heap.allocate(n);
+        }
+        assert_eq!(heap.collection_count(), 0);
+        // The fourth allocation collects, keeping only itself
+        heap.allocate(3u32);
+        assert_eq!(heap.collection_count(), 1);
+        assert_eq!(heap.object_count(), 1);
+
+        heap.set_mode(CollectionMode::Eager);
+        heap.allocate(4u32);
+        heap.allocate(5u32);
+        assert_eq!(heap.collection_count(), 3);
+        assert_eq!(heap.object_count(), 1);
+    }
+
+    std::thread_local! {
+        /// What `record_survivors` was last handed
+        static SURVIVORS: RefCell<Vec<Weak<dyn Trace>>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record_survivors(weaks: Vec<Weak<dyn Trace>>) {
+        SURVIVORS.with(|survivors| *survivors.borrow_mut() = weaks);
+    }
+
+    fn take_survivors() -> Vec<Weak<dyn Trace>> {
+        SURVIVORS.with(|survivors| mem::take(&mut *survivors.borrow_mut()))
+    }
+
+    #[test]
+    fn weak_observer_keeps_nothing_alive() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_weak_observer(record_survivors);
+        let kept = heap.allocate(1u32);
+        heap.register_root(heap.object_of(&kept));
+        heap.allocate(2u32);
+        assert_eq!(heap.collect().objects_freed, 1);
+
+        let survivors = take_survivors();
+        assert_eq!(survivors.len(), 1);
+        assert!(survivors[0].is_alive());
+        heap.unregister_root(heap.object_of(&kept));
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert!(!survivors[0].is_alive());
+        assert!(take_survivors().is_empty());
+    }
+
+    #[test]
+    fn compact_leaves_weak_targets_in_place() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_weak_observer(record_survivors);
+        // Garbage below the handle's object, leaving a hole to move into
+        heap.allocate([0u32; 32]);
+        let handle = heap.allocate_handle([7u32; 32]);
+        let holder = heap.allocate(vec![handle]);
+        heap.register_root(heap.object_of(&holder));
+        assert_eq!(heap.collect().objects_freed, 1);
+
+        let survivors = take_survivors();
+        heap.compact();
+        let target = handle.as_non_null();
+        assert!(survivors
+            .iter()
+            .filter_map(|weak| weak.upgrade())
+            .any(|gc| ptr::addr_eq(gc.as_ptr().as_ptr(), target.as_ptr())));
+
+        // Free to move now, and the flags of the dropped `Weak`s go with it
+        drop(survivors);
+        heap.compact();
+        assert_eq!(handle[31], 7);
+        assert_eq!(heap.collect().objects_freed, 0);
+        take_survivors();
+    }
+
+    /// Holds one mutable edge
+    struct Link {
+        next: GcCell<Option<Gc<dyn Trace>>>,
+    }
+
+    impl Finalize for Link {}
+
+    impl Trace for Link {
+        fn trace(&self, tracer: &mut dyn Tracer) {
+            self.next.trace(tracer);
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn write_barrier_keeps_a_white_object_stored_into_a_black_one() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let black = heap.allocate(Link {
+            next: GcCell::new(None),
+        });
+        heap.register_root(heap.object_of(&black));
+        // Only reachable from here, which is not a root
+        let white = heap.allocate(Canary::new(&drops, None)).unsize();
+
+        heap.start_collection();
+        assert!(heap.step(usize::MAX));
+        *black.next.borrow_mut(&mut heap, &black) = Some(white);
+        let stats = heap.finish_collection();
+        assert_eq!(stats.objects_freed, 0);
+        assert_eq!(drops.get(), 0);
+
+        *black.next.borrow_mut(&mut heap, &black) = None;
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(drops.get(), 1);
+    }
+
+    /// Compiles only while a heap can be built in a `const` context and put
+    /// in a `static`
+    static GLOBAL: GlobalHeap = unsafe { GlobalHeap::new() };
+
+    #[test]
+    fn global_heap_in_a_static() {
+        // No interrupts to mask on the host
+        let cs = unsafe { cortex_m::interrupt::CriticalSection::new() };
+        let mut heap = GLOBAL.borrow(&cs).borrow_mut();
+        heap.set_threshold(1000);
+        let kept = heap.allocate(1u32);
+        let root = heap.object_of(&kept);
+        heap.register_root(root);
+        heap.allocate(2u32);
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(*kept, 1);
+        heap.reset();
+    }
+
+    /// Read by `ticking_clock`
+    #[cfg(feature = "cycle-count")]
+    static CYCLES: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+    /// A cycle counter that advances by 10 on every read
+    #[cfg(feature = "cycle-count")]
+    fn ticking_clock() -> u32 {
+        CYCLES.fetch_add(10, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "cycle-count")]
+    #[test]
+    fn collect_with_budget_stops_early_and_resumes() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_cycle_clock(ticking_clock);
+        let mut last = None;
+        for _ in 0..40 {
+            last = Some(heap.allocate(Canary::new(&drops, last)).unsize());
+            heap.allocate(Canary::new(&drops, None));
+        }
+        heap.register_root(last.unwrap().as_non_null());
+
+        // Marking the chain takes several chunks
+        assert!(!heap.collect_with_budget(5));
+        while !heap.collect_with_budget(5) {}
+        assert_eq!(drops.get(), 40);
+        assert_eq!(heap.object_count(), 40);
+
+        // Sweeping nothing but survivors is bounded too
+        heap.start_collection();
+        assert!(heap.step(usize::MAX));
+        assert!(!heap.collect_with_budget(5));
+        assert!(heap.collect_with_budget(u32::MAX));
+        assert_eq!(heap.object_count(), 40);
+        assert_eq!(drops.get(), 40);
+    }
+
+    #[test]
+    fn root_macro_unroots_at_the_end_of_each_scope() {
+        let drops = Rc::new(Cell::new(0));
+        let heap = RefCell::new(Heap::with_threshold(1000));
+        root!(heap, outer = Canary::new(&drops, None));
+        {
+            root!(heap, inner = Canary::new(&drops, Some(outer.unsize())));
+            assert_eq!(heap.borrow_mut().collect().objects_freed, 0);
+            assert!(inner.child.is_some());
+        }
+        assert_eq!(heap.borrow_mut().collect().objects_freed, 1);
+        assert_eq!(drops.get(), 1);
+        assert_eq!(heap.borrow().root_count(), 1);
+    }
+
+    #[test]
+    fn root_macro_value_may_borrow_the_heap() {
+        let heap = RefCell::new(Heap::with_threshold(1000));
+        root!(heap, count = heap.borrow().object_count());
+        assert_eq!(*count, 0);
+        assert_eq!(heap.borrow_mut().collect().objects_freed, 0);
+    }
+
+    #[test]
+    fn root_macro_evaluates_the_heap_once() {
+        let heap = RefCell::new(Heap::with_threshold(1000));
+        let lookups = Cell::new(0);
+        let lookup = || {
+            lookups.set(lookups.get() + 1);
+            &heap
+        };
+        root!(lookup(), value = 5u32);
+        assert_eq!(lookups.get(), 1);
+        assert_eq!(heap.borrow_mut().collect().objects_freed, 0);
+        assert_eq!(*value, 5);
+    }
+
+    #[test]
+    fn incremental_collection_frees_what_collect_frees() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heaps = [Heap::with_threshold(1000), Heap::with_threshold(1000)];
+        for heap in &mut heaps {
+            let live = chain(heap, &drops, 10);
+            heap.register_root(live.as_non_null());
+            chain(heap, &drops, 7);
+        }
+        let [stop_the_world, incremental] = &mut heaps;
+        let full = stop_the_world.collect();
+        assert_eq!(drops.get(), 7);
+
+        incremental.start_collection();
+        let mut steps = 1;
+        while !incremental.step(1) {
+            steps += 1;
+        }
+        let stepped = incremental.finish_collection();
+        assert!(steps > 1);
+        assert_eq!(stepped.objects_freed, full.objects_freed);
+        assert_eq!(stepped.objects_surviving, full.objects_surviving);
+        assert_eq!(drops.get(), 14);
+    }
+
+    #[test]
+    fn deep_chain_is_marked_without_recursing() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(usize::MAX);
+        let head = chain(&mut heap, &drops, 100_000);
+        heap.register_root(head.as_non_null());
+        assert_eq!(heap.collect().objects_surviving, 100_000);
+
+        heap.unregister_root(head.as_non_null());
+        assert_eq!(heap.collect().objects_freed, 100_000);
+        assert_eq!(drops.get(), 100_000);
+    }
+
+    /// Holds relocatable children
+    struct Handles {
+        kids: Vec<GcHandle<[u32; 32]>>,
+    }
+
+    impl Finalize for Handles {}
+
+    impl Trace for Handles {
+        fn trace(&self, tracer: &mut dyn Tracer) {
+            self.kids.trace(tracer);
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn compacted_handles_still_reach_their_values() {
+        let mut heap = Heap::with_threshold(1000);
+        // Garbage below the children, leaving holes to move into
+        for _ in 0..5 {
+            heap.allocate([0u32; 32]);
+        }
+        let kids: Vec<_> = (0..5).map(|i| heap.allocate_handle([i; 32])).collect();
+        let before: Vec<_> = kids.iter().map(|kid| kid.as_non_null()).collect();
+        let parent = heap.allocate(Handles { kids: kids.clone() });
+        heap.register_root(heap.object_of(&parent));
+        assert_eq!(heap.collect().objects_freed, 5);
+
+        let moved = heap.compact();
+        let after: Vec<_> = kids.iter().map(|kid| kid.as_non_null()).collect();
+        let relocated = before
+            .iter()
+            .zip(&after)
+            .filter(|(old, new)| !ptr::addr_eq(old.as_ptr(), new.as_ptr()));
+        assert_eq!(relocated.count(), moved);
+        for (i, kid) in (0..).zip(&kids) {
+            assert_eq!(**kid, [i; 32]);
+        }
+
+        // The moved boxes are traced and freed at their new addresses
+        assert_eq!(heap.collect().objects_surviving, 6);
+        for (i, kid) in (0..).zip(&parent.kids) {
+            assert_eq!(**kid, [i; 32]);
+        }
+        heap.unregister_root(heap.object_of(&parent));
+        assert_eq!(heap.collect().objects_freed, 6);
+    }
+
+    #[test]
+    fn mark_bitmap_frees_exactly_the_unreachable_objects() {
+        let counters: Vec<_> = (0..64).map(|_| Rc::new(Cell::new(0))).collect();
+        let mut heap = Heap::with_threshold(1000);
+        let objects: Vec<_> = counters
+            .iter()
+            .map(|drops| heap.allocate(Canary::new(drops, None)))
+            .collect();
+        // Spans several bitmap words, and the sweep's renumbering moves the
+        // survivors across them
+        for (i, gc) in objects.iter().enumerate() {
+            if i % 3 == 0 {
+                heap.register_root(heap.object_of(gc));
+            }
+        }
+
+        for _ in 0..2 {
+            let stats = heap.collect();
+            assert_eq!(stats.objects_surviving, 22);
+            for (i, drops) in counters.iter().enumerate() {
+                assert_eq!(drops.get(), usize::from(i % 3 != 0), "object {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn minor_collection_frees_the_young_and_keeps_the_promoted() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_generations(1, 1000);
+        let promoted = heap.allocate(Canary::new(&drops, None));
+        heap.register_root(heap.object_of(&promoted));
+        assert!(heap.collect().minor);
+        heap.unregister_root(heap.object_of(&promoted));
+
+        heap.allocate(Canary::new(&drops, None));
+        let stats = heap.collect_generation(0);
+        assert!(stats.minor);
+        assert_eq!(stats.objects_freed, 1);
+        assert_eq!(drops.get(), 1);
+
+        // Only a major collection reaches the old generation
+        let stats = heap.collect_generation(1);
+        assert!(!stats.minor);
+        assert_eq!(stats.objects_freed, 1);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn weak_dies_with_its_target() {
+        let mut heap = Heap::with_threshold(1000);
+        let target = heap.allocate(5u32);
+        heap.register_root(heap.object_of(&target));
+        let weak = Weak::new(&mut heap, target);
+        let clone = weak.clone();
+        assert_eq!(heap.collect().objects_freed, 0);
+        assert!(weak.is_alive());
+        assert_eq!(weak.upgrade().map(|gc| *gc), Some(5));
+
+        heap.unregister_root(heap.object_of(&target));
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert!(!weak.is_alive());
+        assert!(weak.upgrade().is_none());
+        assert!(!clone.is_alive());
+    }
+
+    #[test]
+    fn sweep_step_frees_at_most_its_budget() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let kept = chain(&mut heap, &drops, 10);
+        for _ in 0..20 {
+            heap.allocate(Canary::new(&drops, None));
+        }
+
+        heap.mark(&[kept.as_non_null()]);
+        for round in 1..=4 {
+            assert_eq!(heap.sweep_step(5), 5);
+            assert_eq!(drops.get(), 5 * round);
+        }
+        assert_eq!(heap.object_count(), 10);
+    }
+
+    /// Half of a two-object cycle that reads its sibling when finalized and
+    /// would when dropped
+    struct Sibling {
+        other: RefCell<Option<Gc<Sibling>>>,
+        value: usize,
+        seen: Rc<Cell<usize>>,
+    }
+
+    impl Finalize for Sibling {
+        fn finalize(&self) {
+            if let Some(other) = &*self.other.borrow() {
+                self.seen.set(self.seen.get() + other.value);
+            }
+        }
+    }
+
+    impl Drop for Sibling {
+        fn drop(&mut self) {
+            // `clear_refs` already let go of the sibling, which may be gone
+            assert!(self.other.borrow().is_none());
+        }
+    }
+
+    impl Trace for Sibling {
+        fn trace(&self, tracer: &mut dyn Tracer) {
+            self.other.trace(tracer);
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn clear_refs(&mut self) {
+            *self.other.get_mut() = None;
+        }
+    }
+
+    #[test]
+    fn garbage_cycle_is_finalized_then_cleared_then_dropped() {
+        let seen = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let first = heap.allocate(Sibling {
+            other: RefCell::new(None),
+            value: 1,
+            seen: seen.clone(),
+        });
+        let second = heap.allocate(Sibling {
+            other: RefCell::new(Some(first)),
+            value: 2,
+            seen: seen.clone(),
+        });
+        *first.other.borrow_mut() = Some(second);
+
+        assert_eq!(heap.collect().objects_freed, 2);
+        // Both finalizers read the other object
+        assert_eq!(seen.get(), 3);
+    }
+
+    /// Counts its drops, and panics in `drop` if armed
+    struct Bomb {
+        drops: Rc<Cell<usize>>,
+        armed: bool,
+    }
+
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+            if self.armed {
+                panic!("bomb dropped");
+            }
+        }
+    }
+
+    impl Finalize for Bomb {}
+
+    impl Trace for Bomb {
+        fn trace(&self, _tracer: &mut dyn Tracer) {}
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn panicking_drop_leaves_the_heap_consistent() {
+        let drops = Rc::new(Cell::new(0));
+        let bomb = |armed| Bomb {
+            drops: drops.clone(),
+            armed,
+        };
+        let mut heap = Heap::with_threshold(1000);
+        let kept = heap.allocate(bomb(false));
+        heap.register_root(heap.object_of(&kept));
+        for i in 0..6 {
+            heap.allocate(bomb(i == 2));
+        }
+
+        let collect = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| heap.collect()));
+        assert!(collect.is_err());
+        // The other garbage is still dropped and freed
+        assert_eq!(drops.get(), 6);
+        assert_eq!(heap.object_count(), 1);
+        assert_eq!(heap.validate(), Ok(()));
+
+        heap.allocate(bomb(false));
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(drops.get(), 7);
+
+        // An incremental sweep resumes where it stopped
+        for i in 0..4 {
+            heap.allocate(bomb(i == 1));
+        }
+        let step = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| heap.sweep_step(10)));
+        assert!(step.is_err());
+        heap.sweep_step(10);
+        assert_eq!(drops.get(), 11);
+        assert_eq!(heap.object_count(), 1);
+    }
+
+    #[test]
+    fn take_moves_out_a_uniquely_rooted_leaf_only() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let leaf = heap.allocate(Canary::new(&drops, None));
+        heap.register_root(heap.object_of(&leaf));
+        let taken = heap.take(leaf).expect("leaf is only rooted");
+        assert_eq!(heap.object_count(), 0);
+        assert_eq!(heap.root_count(), 0);
+        // Moved out, so dropped by the caller rather than the heap
+        assert_eq!(drops.get(), 0);
+        drop(taken);
+        assert_eq!(drops.get(), 1);
+
+        let shared = heap.allocate(Canary::new(&drops, None));
+        let parent = heap.allocate(Canary::new(&drops, Some(shared.unsize())));
+        heap.register_root(heap.object_of(&parent));
+        assert!(heap.take(shared).is_none());
+        assert_eq!(heap.collect().objects_freed, 0);
+        assert_eq!(heap.object_count(), 2);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn bump_scope_reclaims_its_region_at_once() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let first = heap.bump_scope(4096, |bump| {
+            let values: Vec<&u64> = (0..100).map(|i| bump.alloc(i).unwrap()).collect();
+            assert_eq!(values.iter().copied().sum::<u64>(), 4950);
+            assert_eq!(bump.used(), 800);
+            for _ in 0..10 {
+                bump.alloc(Canary::new(&drops, None)).unwrap();
+            }
+            assert!(bump.alloc([0u8; 8192]).is_err());
+            values[0] as *const u64 as usize
+        });
+        // Only the values with a `Drop` impl were remembered and dropped
+        assert_eq!(drops.get(), 10);
+        assert_eq!(heap.object_count(), 0);
+
+        // The same region, back at its start
+        let again = heap.bump_scope(1024, |bump| {
+            assert_eq!(bump.used(), 0);
+            bump.alloc(0u64).unwrap() as *const u64 as usize
+        });
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn downgraded_gc_stops_upgrading_once_collected() {
+        let mut heap = Heap::with_threshold(1000);
+        let target = heap.allocate(3u32);
+        heap.register_root(heap.object_of(&target));
+        let first = target.downgrade(&mut heap);
+        let second = target.downgrade(&mut heap);
+        assert_eq!(heap.collect().objects_freed, 0);
+        assert_eq!(first.upgrade().map(|gc| *gc), Some(3));
+        // The `Weak`s do not root it
+        assert_eq!(heap.root_count(), 1);
+
+        heap.unregister_root(heap.object_of(&target));
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert!(first.upgrade().is_none());
+        assert!(second.upgrade().is_none());
+    }
+
+    #[test]
+    fn counted_is_freed_when_its_last_handle_drops() {
+        let drops = Rc::new(Cell::new(0));
+        let heap = RefCell::new(Heap::with_threshold(1000));
+        let first = Counted::new(&heap, Canary::new(&drops, None));
+        let second = first.clone();
+        assert_eq!(first.strong_count(), 2);
+        // Rooted by its handles
+        assert_eq!(heap.borrow_mut().collect().objects_freed, 0);
+
+        drop(first);
+        assert_eq!(heap.borrow().object_count(), 1);
+        assert_eq!(drops.get(), 0);
+        drop(second);
+        // Without a collection
+        assert_eq!(drops.get(), 1);
+        assert_eq!(heap.borrow().object_count(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn stale_root_is_skipped_in_debug_builds() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let kept = heap.allocate(Canary::new(&drops, None));
+        let stale = heap.allocate(Canary::new(&drops, None));
+        heap.register_root(heap.object_of(&kept));
+        heap.register_root(heap.object_of(&stale));
+        // Fill the free list, so that `stale` goes back to the allocator
+        for _ in 0..FREE_LIST_LIMIT {
+            heap.allocate(Canary::new(&drops, None));
+        }
+        assert_eq!(heap.collect().objects_freed, FREE_LIST_LIMIT);
+        assert_eq!(heap.free_list.len(), FREE_LIST_LIMIT);
+
+        // `mark` ignores the registered roots, so this frees `stale` under
+        // its registration
+        heap.mark(&[heap.object_of(&kept)]);
+        assert_eq!(heap.sweep().objects_freed, 1);
+        assert_eq!(heap.root_count(), 2);
+
+        assert_eq!(heap.collect().objects_freed, 0);
+        assert_eq!(heap.object_count(), 1);
+        assert_eq!(drops.get(), FREE_LIST_LIMIT + 1);
+    }
+
+    #[test]
+    fn allocate_returns_a_typed_handle() {
+        let mut heap = Heap::with_threshold(1000);
+        let pair: Gc<[u32; 2]> = heap.allocate([7, 9]);
+        // No downcast needed to reach the elements
+        assert_eq!(pair[0] + pair[1], 16);
+        let erased: Gc<dyn Trace> = pair.unsize();
+        assert_eq!(erased.downcast_ref::<[u32; 2]>(), Some(&[7, 9]));
+    }
+
+    #[test]
+    fn threshold_grows_with_the_survivors() {
+        let mut heap = Heap::with_threshold(4);
+        heap.set_growth_factor(3);
+        let kept: Vec<_> = (0..3u32).map(|n| heap.allocate(n)).collect();
+        for gc in &kept {
+            heap.register_root(heap.object_of(gc));
+        }
+        assert_eq!(heap.collection_count(), 0);
+        // The fourth allocation reaches the threshold
+        heap.allocate(3u32);
+        assert_eq!(heap.collection_count(), 1);
+        assert_eq!(heap.config().threshold, 12);
+
+        heap.set_threshold(2);
+        assert_eq!(heap.config().threshold, 2);
+        heap.allocate(4u32);
+        heap.allocate(5u32);
+        assert_eq!(heap.collection_count(), 2);
+    }
+
+    #[test]
+    fn downcast_ref_checks_the_allocated_type() {
+        let mut heap = Heap::with_threshold(1000);
+        let erased = heap.allocate(5u16).unsize();
+        assert_eq!(erased.downcast_ref::<u16>(), Some(&5));
+        assert!(erased.downcast_ref::<u32>().is_none());
+        assert!(erased.downcast_ref::<i16>().is_none());
+    }
+
+    #[test]
+    fn vec_and_option_trace_their_handles() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let kids: Vec<Gc<dyn Trace>> = (0..2)
+            .map(|_| heap.allocate(Canary::new(&drops, None)).unsize())
+            .collect();
+        let list = heap.allocate(kids);
+        let only = heap.allocate(Canary::new(&drops, None)).unsize();
+        let maybe = heap.allocate(Some(only));
+        heap.register_root(heap.object_of(&list));
+        heap.register_root(heap.object_of(&maybe));
+        assert_eq!(heap.collect().objects_freed, 0);
+        assert_eq!(drops.get(), 0);
+
+        heap.unregister_root(heap.object_of(&list));
+        heap.unregister_root(heap.object_of(&maybe));
+        assert_eq!(heap.collect().objects_freed, 5);
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn object_count_and_live_bytes_follow_the_heap() {
+        let mut heap = Heap::with_threshold(1000);
+        assert_eq!((heap.object_count(), heap.live_bytes()), (0, 0));
+        let small = heap.allocate(1u32);
+        heap.allocate([0u8; 16]);
+        assert_eq!((heap.object_count(), heap.live_bytes()), (2, 20));
+
+        heap.register_root(heap.object_of(&small));
+        heap.collect();
+        assert_eq!((heap.object_count(), heap.live_bytes()), (1, 4));
+    }
+
+    #[test]
+    fn collect_reports_the_bytes_it_reclaims() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.allocate(1u64);
+        heap.allocate([0u8; 40]);
+        let expected = Layout::new::<GcBox<u64>>().size() + Layout::new::<GcBox<[u8; 40]>>().size();
+        let stats = heap.collect();
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(stats.bytes_freed, expected);
+        assert_eq!(heap.collect().bytes_freed, 0);
+    }
+
+    #[test]
+    fn to_dyn_trace_ptr_keeps_the_vtable_of_t() {
+        let mut heap = Heap::with_threshold(1000);
+        let gc = heap.allocate(9u64);
+        let erased = to_dyn_trace_ptr(gc.as_ptr());
+        let value = unsafe { &erased.as_ref().value };
+        assert_eq!(value.as_any().downcast_ref::<u64>(), Some(&9));
+    }
+
+    #[test]
+    fn gc_cell_edges_follow_their_replacement() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let first = heap.allocate(Canary::new(&drops, None)).unsize();
+        let link = heap.allocate(Link {
+            next: GcCell::new(Some(first)),
+        });
+        heap.register_root(heap.object_of(&link));
+        assert_eq!(heap.collect().objects_freed, 0);
+
+        let second = heap.allocate(Canary::new(&drops, None)).unsize();
+        *link.next.borrow_mut(&mut heap, &link) = Some(second);
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(drops.get(), 1);
+        assert!(link
+            .next
+            .borrow()
+            .is_some_and(|next| Gc::ptr_eq(&next, &second)));
+    }
+
+    #[test]
+    fn ptr_eq_compares_identity_not_value() {
+        let mut heap = Heap::with_threshold(1000);
+        let a = heap.allocate(1u32);
+        let b = heap.allocate(1u32);
+        let copy = a;
+        assert!(Gc::ptr_eq(&a, &copy));
+        assert!(!Gc::ptr_eq(&a, &b));
+        assert!(Gc::ptr_eq(&a.unsize(), &copy.unsize()));
+    }
+
+    #[test]
+    fn iter_objects_lists_every_tracked_value() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.allocate(1u32);
+        heap.allocate(2u8);
+        heap.allocate(3u32);
+        assert_eq!(heap.iter_objects().count(), 3);
+        let mut sum = 0;
+        for n in heap.objects_of_type::<u32>() {
+            sum += n;
+        }
+        assert_eq!(sum, 4);
+
+        heap.collect();
+        assert_eq!(heap.iter_objects().count(), 0);
+    }
+
+    #[test]
+    fn try_allocate_collects_when_due() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(2);
+        heap.try_allocate(Canary::new(&drops, None)).unwrap();
+        assert_eq!(drops.get(), 0);
+        let kept = heap.try_allocate(7u32);
+        assert_eq!(kept.map(|gc| *gc), Ok(7));
+        assert_eq!(drops.get(), 1);
+        assert_eq!(heap.object_count(), 1);
+    }
+
+    #[test]
+    fn swept_slot_is_reused_by_the_next_allocation_of_its_size() {
+        let mut heap = Heap::with_threshold(1000);
+        let freed = heap.allocate(1u64).as_ptr().cast::<u8>();
+        heap.collect();
+        assert_eq!(heap.reused_slots(), 0);
+
+        // A different layout cannot take it
+        heap.allocate(2u8);
+        assert_eq!(heap.reused_slots(), 0);
+        let reused = heap.allocate(3u64).as_ptr().cast::<u8>();
+        assert_eq!(heap.reused_slots(), 1);
+        assert_eq!(reused, freed);
+    }
+
+    std::thread_local! {
+        /// Calls of `count_start`, and the `objects_freed` last seen by
+        /// `count_end`
+        static HOOKS: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+    }
+
+    fn count_start() {
+        HOOKS.with(|hooks| hooks.set((hooks.get().0 + 1, hooks.get().1)));
+    }
+
+    fn count_end(stats: &CollectionStats) {
+        HOOKS.with(|hooks| hooks.set((hooks.get().0, stats.objects_freed)));
+    }
+
+    #[test]
+    fn collection_hooks_run_around_each_collection() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_on_collect_start(count_start);
+        heap.set_on_collect_end(count_end);
+        heap.allocate(1u32);
+        heap.allocate(2u32);
+        heap.collect();
+        assert_eq!(HOOKS.with(Cell::get), (1, 2));
+        heap.collect();
+        assert_eq!(HOOKS.with(Cell::get), (2, 0));
+    }
+
+    /// Read by `pause_clock`
+    #[cfg(feature = "cycle-count")]
+    static PAUSE_CYCLES: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+    /// A cycle counter that advances by 7 on every read
+    #[cfg(feature = "cycle-count")]
+    fn pause_clock() -> u32 {
+        PAUSE_CYCLES.fetch_add(7, Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "cycle-count")]
+    #[test]
+    fn pause_is_timed_with_the_cycle_clock() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_cycle_clock(pause_clock);
+        assert_eq!(heap.last_pause_cycles(), 0);
+        heap.allocate(1u32);
+        heap.collect();
+        assert_eq!(heap.last_pause_cycles(), 7);
+    }
+
+    #[test]
+    fn root_guard_leaves_the_heap_usable_while_alive() {
+        let drops = Rc::new(Cell::new(0));
+        let heap = RefCell::new(Heap::with_threshold(1000));
+        let gc = heap
+            .borrow_mut()
+            .allocate(Canary::new(&drops, None))
+            .unsize();
+        {
+            let _guard = RootGuard::new(&heap, gc);
+            heap.borrow_mut().allocate(Canary::new(&drops, None));
+            assert_eq!(heap.borrow_mut().collect().objects_freed, 1);
+            assert_eq!(heap.borrow().root_count(), 1);
+        }
+        assert_eq!(heap.borrow().root_count(), 0);
+        assert_eq!(heap.borrow_mut().collect().objects_freed, 1);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn vec_deque_and_btree_map_values_are_traced() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let mut queue = VecDeque::new();
+        let mut map = BTreeMap::new();
+        for key in 0..3u8 {
+            queue.push_back(heap.allocate(Canary::new(&drops, None)));
+            map.insert(key, heap.allocate(Canary::new(&drops, None)));
+        }
+        let queue = heap.allocate(queue);
+        let map = heap.allocate(map);
+        heap.register_root(heap.object_of(&queue));
+        heap.register_root(heap.object_of(&map));
+        assert_eq!(heap.collect().objects_freed, 0);
+
+        heap.unregister_root(heap.object_of(&map));
+        assert_eq!(heap.collect().objects_freed, 4);
+        assert_eq!(drops.get(), 3);
+    }
+
+    #[test]
+    fn force_full_collection_ignores_the_threshold() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        for _ in 0..3 {
+            heap.allocate(Canary::new(&drops, None));
+        }
+        assert_eq!(heap.config().allocation_count, 3);
+        assert_eq!(heap.force_full_collection().objects_freed, 3);
+        assert_eq!(drops.get(), 3);
+        assert_eq!(heap.config().allocation_count, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn retain_stats_names_the_survivors_and_the_freed() {
+        let mut heap = Heap::with_threshold(1000);
+        let kept = heap.allocate(1u32);
+        heap.allocate(2u8);
+        heap.allocate(3u64);
+        heap.register_root(heap.object_of(&kept));
+
+        let (mut survivors, mut freed) = (Vec::new(), Vec::new());
+        let stats = heap.retain_stats(&mut survivors, &mut freed);
+        assert_eq!(stats.objects_freed, 2);
+        assert_eq!(survivors, ["u32"]);
+        freed.sort_unstable();
+        assert_eq!(freed, ["u64", "u8"]);
+    }
+
+    #[test]
+    fn with_root_roots_only_inside_the_closure() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let gc = heap.allocate(Canary::new(&drops, None));
+        let freed = heap.with_root(gc, |heap, rooted| {
+            assert!(rooted.child.is_none());
+            heap.collect().objects_freed
+        });
+        assert_eq!(freed, 0);
+        assert_eq!(heap.root_count(), 0);
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn debug_shows_the_address_and_the_value() {
+        let mut heap = Heap::with_threshold(1000);
+        let gc = heap.allocate(5u32);
+        let addr = gc.as_ptr().as_ptr() as usize;
+        assert_eq!(
+            std::format!("{:?}", gc),
+            std::format!("Gc({:#x} -> 5)", addr)
+        );
+    }
+
+    #[test]
+    fn dropping_the_heap_frees_its_objects() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let kept = chain(&mut heap, &drops, 3);
+        heap.register_root(kept.as_non_null());
+        heap.allocate(Canary::new(&drops, None));
+        drop(heap);
+        assert_eq!(drops.get(), 4);
+    }
+
+    #[test]
+    fn mark_and_sweep_can_run_as_separate_phases() {
+        let mut heap = Heap::with_threshold(1000);
+        let kept = heap.allocate(1u32);
+        let lost = heap.allocate(2u32);
+        heap.register_root(heap.object_of(&lost));
+
+        // Only the roots passed in count
+        heap.mark(&[heap.object_of(&kept)]);
+        assert!(heap.is_marked(&kept));
+        assert!(!heap.is_marked(&lost));
+        heap.unregister_root(heap.object_of(&lost));
+        assert_eq!(heap.sweep().objects_freed, 1);
+        assert_eq!(heap.object_count(), 1);
+    }
+
+    #[test]
+    fn access_lends_the_value_for_the_closure() {
+        let mut heap = Heap::with_threshold(1000);
+        let gc = heap.allocate([1u8, 2, 3]);
+        let sum = heap.access(&gc, |bytes| bytes.iter().sum::<u8>());
+        assert_eq!(sum, 6);
+    }
+
+    #[test]
+    fn reachable_from_counts_each_object_once() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let head = chain(&mut heap, &drops, 4);
+        heap.allocate(Canary::new(&drops, None));
+        assert_eq!(heap.reachable_from(head), 4);
+
+        // Two paths to the same chain
+        let left = heap.allocate(Canary::new(&drops, Some(head))).unsize();
+        let right = heap.allocate(Canary::new(&drops, Some(head))).unsize();
+        let top = heap.allocate(vec![left, right]).unsize();
+        assert_eq!(heap.reachable_from(top), 7);
+        assert_eq!(heap.object_count(), 8);
+    }
+
+    #[test]
+    fn allocate_many_keeps_its_whole_batch() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(3);
+        let batch = heap.allocate_many((0..5).map(|_| Canary::new(&drops, None)));
+        // The batch crossed the threshold and collected once, freeing nothing
+        assert_eq!(batch.len(), 5);
+        assert_eq!(heap.collection_count(), 1);
+        assert_eq!(drops.get(), 0);
+        assert_eq!(heap.collect().objects_freed, 5);
+    }
+
+    #[test]
+    fn shrink_to_fit_releases_spare_capacity() {
+        let mut heap = Heap::with_threshold(1000);
+        for n in 0..64u32 {
+            heap.allocate(n);
+        }
+        heap.collect();
+        assert!(heap.capacity() >= 64);
+        assert!(heap.shrink_to_fit() > 0);
+        assert_eq!(heap.capacity(), 0);
+        assert_eq!(heap.shrink_to_fit(), 0);
+    }
+
+    #[test]
+    fn collect_cycles_frees_an_unrooted_cycle() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let a = heap.allocate(Link {
+            next: GcCell::new(None),
+        });
+        let b = heap.allocate(Link {
+            next: GcCell::new(Some(a.unsize())),
+        });
+        *a.next.borrow_mut(&mut heap, &a) = Some(b.unsize());
+        heap.allocate(Canary::new(&drops, None));
+        assert_eq!(heap.collect_cycles().objects_freed, 3);
+        assert_eq!(heap.object_count(), 0);
+    }
+
+    #[test]
+    fn config_reports_the_trigger_and_the_contents() {
+        let mut heap = Heap::with_threshold(10);
+        let kept = heap.allocate(1u32);
+        heap.allocate(2u32);
+        heap.register_root(heap.object_of(&kept));
+        let config = heap.config();
+        assert_eq!(config.threshold, 10);
+        assert_eq!(config.allocation_count, 2);
+        assert_eq!(config.object_count, 2);
+        assert_eq!(config.root_count, 1);
+    }
+
+    #[test]
+    fn heaps_collect_independently() {
+        let drops = Rc::new(Cell::new(0));
+        let mut first = Heap::with_threshold(1000);
+        let mut second = Heap::with_threshold(1000);
+        first.allocate(Canary::new(&drops, None));
+        let kept = second.allocate(Canary::new(&drops, None));
+        second.register_root(second.object_of(&kept));
+        assert_eq!(second.collect().objects_freed, 0);
+        assert_eq!(first.object_count(), 1);
+        assert_eq!(first.collect().objects_freed, 1);
+        assert_eq!(second.object_count(), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "belongs to another heap")]
+    fn rooting_another_heaps_object_panics() {
+        let mut first = Heap::with_threshold(1000);
+        let mut second = Heap::with_threshold(1000);
+        let gc = first.allocate(1u32);
+        second.register_root(first.object_of(&gc));
+    }
+
+    #[test]
+    fn eq_compares_the_pointees() {
+        let mut heap = Heap::with_threshold(1000);
+        let a = heap.allocate(4u32);
+        let b = heap.allocate(4u32);
+        let c = heap.allocate(5u32);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(!Gc::ptr_eq(&a, &b));
+    }
+
+    /// A leaf type with neither a `Trace` nor a `Finalize` impl of its own
+    struct Reading(u16);
+
+    impl TraceLeaf for Reading {}
+
+    #[test]
+    fn trace_leaf_types_allocate_without_impls() {
+        let mut heap = Heap::with_threshold(1000);
+        let kept = heap.allocate(Reading(3));
+        heap.allocate(Reading(4));
+        heap.register_root(heap.object_of(&kept));
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(kept.0, 3);
+    }
+
+    #[test]
+    fn pinned_root_survives_explicit_root_sets_until_unpinned() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let pinned = heap.allocate(Canary::new(&drops, None));
+        heap.pin_root(pinned);
+        heap.unregister_root(heap.object_of(&pinned));
+        assert_eq!(heap.collect_garbage(&[]).objects_freed, 0);
+        assert_eq!(heap.collect().objects_freed, 0);
+
+        heap.unpin_root(pinned);
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[test]
+    fn root_set_stays_sorted_and_counted() {
+        let mut heap = Heap::with_threshold(1000);
+        let gcs: Vec<Gc<dyn Trace>> = (0..50u32).map(|n| heap.allocate(n).unsize()).collect();
+        // Registered out of address order, some twice
+        for gc in gcs.iter().rev().chain(gcs.iter().step_by(2)) {
+            heap.register_root(gc.as_non_null());
+        }
+        assert_eq!(heap.root_count(), 50);
+        assert!(gcs.iter().all(|&gc| heap.is_rooted(gc)));
+
+        for gc in &gcs {
+            heap.unregister_root(gc.as_non_null());
+        }
+        assert_eq!(heap.root_count(), 25);
+        assert_eq!(heap.collect().objects_freed, 25);
+        assert!(gcs.iter().step_by(2).all(|&gc| heap.is_rooted(gc)));
+    }
+
+    #[test]
+    fn from_addr_finds_the_object_at_as_ptr() {
+        let mut heap = Heap::with_threshold(1000);
+        let gc = heap.allocate(8u32);
+        let addr = gc.as_ptr().as_ptr() as usize;
+        let found = unsafe { Gc::from_addr(addr, &heap) };
+        assert!(found.is_some_and(|found| Gc::ptr_eq(&found, &gc.unsize())));
+        assert!(unsafe { Gc::from_addr(addr + 1, &heap) }.is_none());
+    }
+
+    #[test]
+    fn root_set_roots_its_batch_until_dropped() {
+        let drops = Rc::new(Cell::new(0));
+        let heap = RefCell::new(Heap::with_threshold(1000));
+        {
+            let mut roots = RootSet::new(&heap);
+            for _ in 0..3 {
+                let gc = heap.borrow_mut().allocate(Canary::new(&drops, None));
+                roots.push(gc);
+            }
+            assert_eq!(heap.borrow().root_count(), 0);
+            roots.finalize();
+            assert_eq!(heap.borrow().root_count(), 3);
+            assert_eq!(heap.borrow_mut().collect().objects_freed, 0);
+        }
+        assert_eq!(heap.borrow().root_count(), 0);
+        assert_eq!(heap.borrow_mut().collect().objects_freed, 3);
+    }
+
+    #[test]
+    fn with_capacity_does_not_grow_within_its_bounds() {
+        let mut heap = Heap::with_capacity(16, 4);
+        heap.set_threshold(1000);
+        let objects = heap.objects.as_ptr();
+        let roots = heap.roots.as_ptr();
+        for n in 0..16u32 {
+            let gc = heap.allocate(n);
+            if n % 4 == 0 {
+                heap.register_root(heap.object_of(&gc));
+            }
+        }
+        assert_eq!(heap.objects.as_ptr(), objects);
+        assert_eq!(heap.roots.as_ptr(), roots);
+        assert_eq!(heap.capacity(), 16);
+    }
+
+    #[cfg(debug_assertions)]
+    std::thread_local! {
+        /// Lines received by `LineCounter`
+        static LOGGED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[cfg(debug_assertions)]
+    struct LineCounter;
+
+    #[cfg(debug_assertions)]
+    impl GcLogger for LineCounter {
+        fn log(&self, _args: fmt::Arguments) {
+            LOGGED.with(|logged| logged.set(logged.get() + 1));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn diagnostics_go_to_the_configured_logger() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_logger(&LineCounter);
+        heap.allocate(1u32);
+        assert_eq!(LOGGED.with(Cell::get), 0);
+        // Tearing down a non-empty heap is reported
+        drop(heap);
+        assert_eq!(LOGGED.with(Cell::get), 1);
+    }
+
+    #[test]
+    fn is_rooted_counts_registered_and_pinned_roots() {
+        let mut heap = Heap::with_threshold(1000);
+        let registered = heap.allocate(1u32).unsize();
+        let pinned = heap.allocate(2u32).unsize();
+        let neither = heap.allocate(3u32).unsize();
+        heap.register_root(registered.as_non_null());
+        heap.pin_root(pinned);
+        assert!(heap.is_rooted(registered));
+        assert!(heap.is_rooted(pinned));
+        assert!(!heap.is_rooted(neither));
+
+        heap.unregister_root(registered.as_non_null());
+        assert!(!heap.is_rooted(registered));
+    }
+
+    /// Holds a `Gc` that its `Trace` impl forgets to visit
+    #[cfg(debug_assertions)]
+    struct Forgetful {
+        _hidden: Gc<dyn Trace>,
+    }
+
+    #[cfg(debug_assertions)]
+    impl Finalize for Forgetful {}
+
+    #[cfg(debug_assertions)]
+    impl Trace for Forgetful {
+        fn trace(&self, _tracer: &mut dyn Tracer) {}
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn verify_tracing_reports_a_skipped_edge() {
+        let mut heap = Heap::with_threshold(1000);
+        let hidden = heap.allocate(1u64).unsize();
+        let holder = heap.allocate(Forgetful { _hidden: hidden });
+        let honest = heap.allocate(Some(hidden));
+
+        heap.mark(&[heap.object_of(&honest)]);
+        assert_eq!(heap.verify_tracing(), 0);
+        heap.mark(&[heap.object_of(&holder)]);
+        assert_eq!(heap.verify_tracing(), 1);
+        heap.sweep();
+    }
+
+    #[test]
+    fn project_borrows_a_part_of_the_right_type_only() {
+        let mut heap = Heap::with_threshold(1000);
+        let erased = heap.allocate([3u16, 4]).unsize();
+        assert_eq!(erased.project(|pair: &[u16; 2]| &pair[1]), Some(&4));
+        assert!(erased.project(|value: &u16| value).is_none());
+    }
+
+    #[test]
+    fn reset_frees_rooted_objects_and_forgets_the_roots() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let kept = chain(&mut heap, &drops, 3);
+        heap.register_root(kept.as_non_null());
+        heap.allocate_pinned(Canary::new(&drops, None));
+        assert_eq!(heap.reset(), 4);
+        assert_eq!(drops.get(), 4);
+        assert_eq!(heap.root_count(), 0);
+        assert_eq!(heap.config().threshold, 1000);
+
+        // Still usable
+        heap.allocate(1u32);
+        assert_eq!(heap.collect().objects_freed, 1);
+    }
+
+    /// A cyclic list node that `deep_clone` can copy
+    struct Ring {
+        id: u32,
+        next: GcCell<Option<Gc<dyn Trace>>>,
+    }
+
+    impl Finalize for Ring {}
+
+    impl Trace for Ring {
+        fn trace(&self, tracer: &mut dyn Tracer) {
+            self.next.trace(tracer);
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn clone_object(&self, cloner: &mut Cloner<'_>) -> Option<Gc<dyn Trace>> {
+            Some(cloner.copy(self).unsize())
+        }
+    }
+
+    impl TraceClone for Ring {
+        fn clone_with(&self, cloner: &mut Cloner<'_>) -> Self {
+            Ring {
+                id: self.id,
+                next: GcCell::new(self.next.borrow().map(|next| cloner.clone_dyn(next))),
+            }
+        }
+    }
+
+    #[test]
+    fn deep_clone_copies_a_cycle_once() {
+        let mut heap = Heap::with_threshold(1000);
+        let a = heap.allocate(Ring {
+            id: 1,
+            next: GcCell::new(None),
+        });
+        let b = heap.allocate(Ring {
+            id: 2,
+            next: GcCell::new(Some(a.unsize())),
+        });
+        *a.next.borrow_mut(&mut heap, &a) = Some(b.unsize());
+
+        let copy = heap.deep_clone(a.unsize());
+        assert_eq!(heap.object_count(), 4);
+        assert!(!Gc::ptr_eq(&copy, &a.unsize()));
+        let ring = copy.downcast_ref::<Ring>().unwrap();
+        let next = ring.next.borrow().unwrap();
+        let back = next.downcast_ref::<Ring>().unwrap().next.borrow().unwrap();
+        assert_eq!(ring.id, 1);
+        assert_eq!(next.downcast_ref::<Ring>().map(|ring| ring.id), Some(2));
+        assert!(Gc::ptr_eq(&back, &copy));
+    }
+
+    /// A millisecond tick that reads 42
+    fn fixed_tick() -> u32 {
+        42
+    }
+
+    #[test]
+    fn last_collection_tick_is_read_after_each_collection() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_collection_clock(fixed_tick);
+        assert_eq!(heap.last_collection_tick(), None);
+        assert_eq!(heap.collection_count(), 0);
+        heap.collect();
+        heap.collect();
+        assert_eq!(heap.last_collection_tick(), Some(42));
+        assert_eq!(heap.collection_count(), 2);
+    }
+
+    #[test]
+    fn nested_gc_traces_through_every_level() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let inner = heap.allocate(Canary::new(&drops, None));
+        let middle = heap.allocate(inner);
+        let outer = heap.allocate(middle);
+        heap.register_root(heap.object_of(&outer));
+        assert_eq!(heap.collect().objects_freed, 0);
+        assert!(outer.child.is_none());
+
+        heap.unregister_root(heap.object_of(&outer));
+        assert_eq!(heap.collect().objects_freed, 3);
+        assert_eq!(drops.get(), 1);
+    }
+
+    /// An allocator that reports 1 KiB in use
+    fn kibibyte_used() -> usize {
+        1024
+    }
+
+    #[test]
+    fn fragmentation_ratio_compares_boxes_with_the_allocator() {
+        let mut heap = Heap::with_threshold(1000);
+        assert_eq!(heap.fragmentation_ratio(), 0.0);
+        heap.set_allocator_stats(kibibyte_used);
+        assert_eq!(heap.fragmentation_ratio(), 1.0);
+
+        heap.allocate([0u8; 256]);
+        let boxed = Layout::new::<GcBox<[u8; 256]>>().size();
+        let expected = 1.0 - boxed as f32 / 1024.0;
+        assert!((heap.fragmentation_ratio() - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn leaked_root_guard_keeps_its_registration() {
+        let heap = RefCell::new(Heap::with_threshold(1000));
+        let gc = heap.borrow_mut().allocate(1u32).unsize();
+        let ptr = RootGuard::new(&heap, gc).leak();
+        assert_eq!(heap.borrow_mut().collect().objects_freed, 0);
+        assert!(heap.borrow().is_rooted(gc));
+
+        heap.borrow_mut().unregister_root(ptr);
+        assert_eq!(heap.borrow_mut().collect().objects_freed, 1);
+    }
+
+    #[test]
+    fn validate_reports_a_stale_mark() {
+        let mut heap = Heap::with_threshold(1000);
+        let kept = heap.allocate(1u32);
+        heap.register_root(heap.object_of(&kept));
+        heap.allocate(2u32);
+        assert_eq!(heap.validate(), Ok(()));
+        heap.collect();
+        assert_eq!(heap.validate(), Ok(()));
+
+        heap.tracer.marks[0] |= 1;
+        assert_eq!(heap.validate(), Err(HeapError::StaleMark { slot: 0 }));
+        heap.tracer.marks[0] = 0;
+    }
+
+    #[test]
+    fn allocate_pinned_survives_until_unpinned() {
+        let mut heap = Heap::with_threshold(1000);
+        let gc = heap.allocate_pinned(7u32);
+        assert!(heap.is_rooted(gc.unsize()));
+        assert_eq!(heap.collect().objects_freed, 0);
+        heap.unpin_root(gc);
+        assert_eq!(heap.collect().objects_freed, 1);
+    }
+
+    #[test]
+    fn default_heap_matches_new() {
+        let heap = Heap::default();
+        assert_eq!(heap.mode(), Heap::new().mode());
+        assert_eq!(heap.config(), Heap::new().config());
+    }
+
+    #[test]
+    fn trace_graph_dot_lists_nodes_and_edges() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let head = chain(&mut heap, &drops, 2);
+        heap.register_root(head.as_non_null());
+
+        let mut dot = std::string::String::new();
+        heap.trace_graph_dot(&mut dot).unwrap();
+        assert!(dot.starts_with("digraph gc {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("    n1 -> n0;\n"));
+        assert_eq!(dot.matches("style=bold").count(), 1);
+        assert_eq!(dot.matches("[label=").count(), 2);
+    }
+
+    #[test]
+    fn new_in_allocates_like_allocate() {
+        let mut heap = Heap::with_threshold(1000);
+        let gc = Gc::new_in(6u8, &mut heap);
+        assert_eq!(*gc, 6);
+        assert_eq!(heap.object_count(), 1);
+    }
+
+    #[test]
+    fn snapshot_detects_growth_since_it_was_taken() {
+        let mut heap = Heap::with_threshold(1000);
+        let kept = heap.allocate(1u32);
+        heap.register_root(heap.object_of(&kept));
+        let snapshot = heap.snapshot();
+
+        let leaked = heap.allocate(2u32);
+        heap.register_root(heap.object_of(&leaked));
+        heap.collect();
+        assert!(!snapshot.is_balanced(&heap));
+
+        heap.unregister_root(heap.object_of(&leaked));
+        heap.collect();
+        snapshot.assert_balanced(&heap);
+    }
+
+    #[test]
+    #[should_panic(expected = "heap grew by 1 objects and 0 roots")]
+    fn assert_balanced_reports_the_growth() {
+        let mut heap = Heap::with_threshold(1000);
+        let snapshot = heap.snapshot();
+        heap.allocate(1u32);
+        snapshot.assert_balanced(&heap);
+    }
+
+    /// More strictly aligned than any header field
+    #[repr(align(64))]
+    struct Aligned(u8);
+
+    impl TraceLeaf for Aligned {}
+
+    #[test]
+    fn over_aligned_values_are_aligned() {
+        let mut heap = Heap::with_threshold(1000);
+        for n in 0..4 {
+            let gc = heap.allocate(Aligned(n));
+            assert_eq!(&*gc as *const Aligned as usize % 64, 0);
+            assert_eq!(gc.0, n);
+        }
+        heap.collect();
+        // A reused slot keeps the alignment
+        let gc = heap.allocate(Aligned(9));
+        assert_eq!(&*gc as *const Aligned as usize % 64, 0);
+    }
+
+    #[test]
+    fn for_each_root_visits_registered_then_pinned() {
+        let mut heap = Heap::with_threshold(1000);
+        let registered = heap.allocate(1u32);
+        heap.register_root(heap.object_of(&registered));
+        heap.allocate_pinned(2u32);
+        heap.allocate(3u32);
+
+        let mut seen = Vec::new();
+        heap.for_each_root(|value| seen.extend(value.as_any().downcast_ref::<u32>().copied()));
+        assert_eq!(seen, [1, 2]);
+    }
+
+    #[test]
+    fn unregistered_roots_are_purged_lazily() {
+        let mut heap = Heap::with_threshold(1000);
+        let gcs: Vec<_> = (0..4u32).map(|n| heap.allocate(n)).collect();
+        for gc in &gcs {
+            heap.register_root(heap.object_of(gc));
+        }
+        heap.unregister_root(heap.object_of(&gcs[1]));
+        heap.unregister_root(heap.object_of(&gcs[2]));
+        // Tombstoned in place until the next collection
+        assert_eq!(heap.root_count(), 2);
+        assert_eq!(heap.roots.len(), 4);
+        assert!(!heap.is_rooted(gcs[1].unsize()));
+
+        // Re-registering revives the tombstone
+        heap.register_root(heap.object_of(&gcs[1]));
+        assert_eq!(heap.root_count(), 3);
+        assert_eq!(heap.collect().objects_freed, 1);
+        assert_eq!(heap.roots.len(), 3);
+    }
+
+    #[test]
+    fn gc_borrows_its_pointee_for_generic_code() {
+        fn total<B: Borrow<u32>>(values: &[B]) -> u32 {
+            values.iter().map(|value| *value.borrow()).sum()
+        }
+
+        let mut heap = Heap::with_threshold(1000);
+        let gcs = [heap.allocate(2u32), heap.allocate(3u32)];
+        assert_eq!(total(&gcs), 5);
+        let s: &u32 = gcs[0].as_ref();
+        assert_eq!(*s, 2);
+    }
+
+    #[test]
+    fn promotion_waits_for_the_configured_survivals() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_generations(1, 1000);
+        heap.set_promote_threshold(3);
+        let young = heap.allocate(Canary::new(&drops, None));
+        heap.register_root(heap.object_of(&young));
+        heap.collect_generation(0);
+        heap.collect_generation(0);
+
+        // Two survivals of three: still young, so a minor collection frees it
+        heap.unregister_root(heap.object_of(&young));
+        assert_eq!(heap.collect_generation(0).objects_freed, 1);
+
+        let old = heap.allocate(Canary::new(&drops, None));
+        heap.register_root(heap.object_of(&old));
+        for _ in 0..3 {
+            heap.collect_generation(0);
+        }
+        heap.unregister_root(heap.object_of(&old));
+        assert_eq!(heap.collect_generation(0).objects_freed, 0);
+        assert_eq!(heap.collect_generation(1).objects_freed, 1);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn cast_unchecked_reinterprets_a_checked_handle() {
+        let mut heap = Heap::with_threshold(1000);
+        let erased = heap.allocate(11u32).unsize();
+        assert!(erased.downcast_ref::<u32>().is_some());
+        let typed: Gc<u32> = unsafe { erased.cast_unchecked::<u32>() };
+        assert_eq!(*typed, 11);
+        assert!(Gc::ptr_eq(&typed.unsize(), &erased));
+    }
+
+    #[test]
+    fn ref_cell_traces_its_contents() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let first = heap.allocate(Canary::new(&drops, None)).unsize();
+        let cell = heap.allocate(RefCell::new(vec![first]));
+        heap.register_root(heap.object_of(&cell));
+        assert_eq!(heap.collect().objects_freed, 0);
+
+        let second = heap.allocate(Canary::new(&drops, None)).unsize();
+        cell.borrow_mut().push(second);
+        assert_eq!(heap.collect().objects_freed, 0);
+        cell.borrow_mut().clear();
+        assert_eq!(heap.collect().objects_freed, 2);
+        assert_eq!(drops.get(), 2);
+    }
+
+    #[test]
+    fn adaptive_threshold_follows_the_allocation_rate() {
+        let mut heap = Heap::with_threshold(1);
+        // Only the collections below count towards the rate
+        heap.set_mode(CollectionMode::Manual);
+        heap.set_adaptive_threshold(true);
+        assert_eq!(heap.allocation_rate(), 0);
+        for _ in 0..3 {
+            for n in 0..6u32 {
+                heap.allocate(n);
+            }
+            heap.collect();
+        }
+        assert_eq!(heap.allocation_rate(), 6);
+        assert_eq!(heap.config().threshold, 6);
+    }
+
+    /// Collects what a `Trace` impl reports, without marking anything
+    struct Recorder {
+        seen: Vec<Gc<dyn Trace>>,
+    }
+
+    impl Tracer for Recorder {
+        fn visit(&mut self, gc: Gc<dyn Trace>) {
+            self.seen.push(gc);
+        }
+    }
+
+    #[test]
+    fn custom_tracer_sees_every_direct_edge() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let leaf = heap.allocate(Canary::new(&drops, None)).unsize();
+        let kids = heap.allocate(vec![leaf, leaf]);
+        let top = Canary::new(&drops, Some(kids.unsize()));
+
+        let mut recorder = Recorder { seen: Vec::new() };
+        top.trace(&mut recorder);
+        assert_eq!(recorder.seen.len(), 1);
+        assert!(Gc::ptr_eq(&recorder.seen[0], &kids.unsize()));
+
+        recorder.seen.clear();
+        // The vector itself, not the handle to it
+        (*kids).trace(&mut recorder);
+        assert_eq!(recorder.seen.len(), 2);
+        assert!(recorder.seen.iter().all(|gc| Gc::ptr_eq(gc, &leaf)));
+    }
+
+    #[test]
+    fn object_allocated_during_marking_keeps_what_it_is_built_with() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let x = heap.allocate(Canary::new(&drops, None)).unsize();
+        let old = heap.allocate(Link {
+            next: GcCell::new(Some(x)),
+        });
+        heap.register_root(heap.object_of(&old));
+
+        heap.start_collection();
+        let y = heap.allocate(Canary::new(&drops, Some(x)));
+        heap.register_root(heap.object_of(&y));
+        // Now only `y` refers to `x`
+        *old.next.borrow_mut(&mut heap, &old) = None;
+        let stats = heap.finish_collection();
+        assert_eq!(stats.objects_freed, 0);
+        assert_eq!(drops.get(), 0);
+        assert!(y
+            .child
+            .is_some_and(|child| heap.is_tracked(child.as_ptr().as_ptr())));
+    }
+
+    #[test]
+    fn write_barrier_covers_an_object_allocated_during_marking() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let x = heap.allocate(Canary::new(&drops, None)).unsize();
+        let old = heap.allocate(Link {
+            next: GcCell::new(Some(x)),
+        });
+        heap.register_root(heap.object_of(&old));
+
+        heap.start_collection();
+        let young = heap.allocate(Link {
+            next: GcCell::new(None),
+        });
+        heap.register_root(heap.object_of(&young));
+        // Scans `young`, the last object shaded, but not `old`
+        assert!(!heap.step(1));
+        assert!(!heap.is_marked(&x));
+
+        *young.next.borrow_mut(&mut heap, &young) = Some(x);
+        *old.next.borrow_mut(&mut heap, &old) = None;
+        assert_eq!(heap.finish_collection().objects_freed, 0);
+        assert_eq!(drops.get(), 0);
+        assert!(young
+            .next
+            .borrow()
+            .is_some_and(|next| heap.is_tracked(next.as_ptr().as_ptr())));
+    }
+
+    std::thread_local! {
+        /// Blocks of `BIG_BLOCK` bytes or more that `CappedAllocator` still
+        /// grants on this thread; unlimited while `None`
+        static BIG_BLOCKS_LEFT: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    const BIG_BLOCK: usize = 1024;
+
+    /// The system allocator, running out of big blocks on request
+    struct CappedAllocator;
+
+    unsafe impl std::alloc::GlobalAlloc for CappedAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if layout.size() >= BIG_BLOCK {
+                let left = BIG_BLOCKS_LEFT.try_with(Cell::get).ok().flatten();
+                match left {
+                    Some(0) => return ptr::null_mut(),
+                    Some(n) => BIG_BLOCKS_LEFT.with(|left| left.set(Some(n - 1))),
+                    None => {}
+                }
+            }
+            unsafe { std::alloc::System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CappedAllocator = CappedAllocator;
+
+    #[test]
+    fn try_allocate_reports_an_exhausted_allocator() {
+        let mut heap = Heap::with_threshold(1000);
+        BIG_BLOCKS_LEFT.with(|left| left.set(Some(4)));
+        let mut allocated = 0;
+        let result = loop {
+            match heap.try_allocate([7u8; BIG_BLOCK]) {
+                Ok(gc) => {
+                    heap.register_root(heap.object_of(&gc));
+                    allocated += 1;
+                }
+                Err(err) => break err,
+            }
+        };
+        BIG_BLOCKS_LEFT.with(|left| left.set(None));
+        assert_eq!(result, AllocError);
+        assert_eq!(allocated, 4);
+        // The failed attempt left the heap as it was
+        assert_eq!(heap.object_count(), 4);
+        assert_eq!(heap.validate(), Ok(()));
+    }
+
+    /// A child pointing back at its parent
+    struct Child {
+        drops: Rc<Cell<usize>>,
+        parent: GcCell<Option<Weak<dyn Trace>>>,
+    }
+
+    impl Drop for Child {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    impl Finalize for Child {}
+
+    impl Trace for Child {
+        fn trace(&self, tracer: &mut dyn Tracer) {
+            self.parent.trace(tracer);
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn parent_and_child_with_a_weak_back_link_die_together() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        let child = heap.allocate(Child {
+            drops: drops.clone(),
+            parent: GcCell::new(None),
+        });
+        let parent = heap
+            .allocate(Canary::new(&drops, Some(child.unsize())))
+            .unsize();
+        let back = parent.downgrade(&mut heap);
+        *child.parent.borrow_mut(&mut heap, &child) = Some(back.clone());
+        heap.register_root(parent.as_non_null());
+        assert_eq!(heap.collect().objects_freed, 0);
+        assert!(back.upgrade().is_some());
+
+        heap.unregister_root(parent.as_non_null());
+        assert_eq!(heap.collect().objects_freed, 2);
+        assert_eq!(drops.get(), 2);
+        assert!(back.upgrade().is_none());
+    }
+
+    #[test]
+    fn box_records_the_type_id_it_was_allocated_with() {
+        let drops = Rc::new(Cell::new(0));
+        let mut heap = Heap::with_threshold(1000);
+        // Stands in for the example's `MyData`
+        let canary = heap.allocate(Canary::new(&drops, None));
+        let erased = canary.unsize();
+        let obj = unsafe { erased.as_ptr().as_ref() };
+        assert_eq!(obj.value_type_id(), TypeId::of::<Canary>());
+        assert_ne!(obj.value_type_id(), TypeId::of::<Link>());
+    }
+
+    #[cfg(feature = "semihosting-log")]
+    std::thread_local! {
+        /// Lines received by `LineRecorder`
+        static RECORDED: RefCell<Vec<std::string::String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Keeps every line instead of printing it over semihosting
+    #[cfg(feature = "semihosting-log")]
+    struct LineRecorder;
+
+    #[cfg(feature = "semihosting-log")]
+    impl GcLogger for LineRecorder {
+        fn log(&self, args: fmt::Arguments) {
+            RECORDED.with(|lines| lines.borrow_mut().push(std::format!("{}", args)));
+        }
+    }
+
+    #[cfg(feature = "semihosting-log")]
+    #[test]
+    fn dump_starts_with_the_object_count() {
+        let mut heap = Heap::with_threshold(1000);
+        heap.set_logger(&LineRecorder);
+        let kept = heap.allocate(1u32);
+        heap.register_root(heap.object_of(&kept));
+        heap.allocate(2u32);
+        heap.dump();
+
+        let lines = RECORDED.with(|lines| lines.take());
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            "gc: 2 objects, 1 roots, threshold 1000, 2 allocations since last collection"
+        );
+        assert!(lines[1].starts_with("gc:   [0] "));
+    }
+
+    #[test]
+    fn word_wise_reset_clears_every_mark_of_300_objects() {
+        let mut heap = Heap::with_threshold(1000);
+        let roots: Vec<_> = (0..300u32)
+            .map(|n| heap.allocate(n).as_non_null())
+            .collect();
+        heap.mark(&roots);
+        assert_eq!(heap.tracer.marks.len(), 300usize.div_ceil(BITS_PER_WORD));
+        let set: u32 = heap.tracer.marks.iter().map(|word| word.count_ones()).sum();
+        assert_eq!(set, 300);
+
+        // Same bits as clearing one object at a time
+        let mut one_by_one = MarkTracer::new(&NoopLogger);
+        one_by_one.marks = heap.tracer.marks.clone();
+        for slot in 100..300 {
+            one_by_one.clear_mark(slot);
+        }
+        heap.tracer.clear_marks_from(100);
+        assert_eq!(heap.tracer.marks, one_by_one.marks);
+
+        heap.tracer.clear_marks_from(0);
+        assert!(heap.tracer.marks.iter().all(|&word| word == 0));
+        // Nothing is marked any more
+        assert_eq!(heap.sweep().objects_freed, 300);
+    }
+
+    #[test]
+    fn marking_terminates_on_a_cycle_and_a_self_loop() {
+        let mut heap = Heap::with_threshold(1000);
+        let a = heap.allocate(Link {
+            next: GcCell::new(None),
+        });
+        let b = heap.allocate(Link {
+            next: GcCell::new(Some(a.unsize())),
+        });
+        *a.next.borrow_mut(&mut heap, &a) = Some(b.unsize());
+        let selfish = heap.allocate(Link {
+            next: GcCell::new(None),
+        });
+        *selfish.next.borrow_mut(&mut heap, &selfish) = Some(selfish.unsize());
+
+        heap.register_root(heap.object_of(&a));
+        heap.register_root(heap.object_of(&selfish));
+        let stats = heap.collect();
+        assert_eq!((stats.objects_freed, stats.objects_surviving), (0, 3));
+        assert_eq!(heap.reachable_from(selfish.unsize()), 1);
+
+        heap.unregister_root(heap.object_of(&a));
+        heap.unregister_root(heap.object_of(&selfish));
+        assert_eq!(heap.collect().objects_freed, 3);
+        assert_eq!(heap.object_count(), 0);
     }
 }
@@ -1,2 +1,2 @@
-#![no_std]
-pub mod gc;
\ No newline at end of file
+#![cfg_attr(not(test), no_std)]
+pub mod gc;
@@ -1,26 +1,45 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(alloc_error_handler)]
 
 extern crate alloc;
 
+#[cfg(not(test))]
 use cortex_m_rt::entry;
-use cortex_m_semihosting::hprintln;
+#[cfg(not(test))]
 use panic_halt as _;
+#[cfg(not(test))]
 use linked_list_allocator::LockedHeap;
 
 use alloc::boxed::Box;
 use core::ptr::NonNull;
-use core::ops::Deref;
 
-use embedded::gc::{Gc, Heap, MyData, RootGuard};
+use core::any::Any;
+use core::cell::RefCell;
+use embedded::gc::{CollectionStats, Finalize, Gc, Heap, Trace, Tracer};
+use embedded::root;
+
+/// Print over semihosting with the `semihosting-log` feature; otherwise the
+/// output vanishes, as the library's diagnostics do
+macro_rules! say {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "semihosting-log")]
+        cortex_m_semihosting::hprintln!($($arg)*);
+        #[cfg(not(feature = "semihosting-log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
 
+#[cfg(not(test))]
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+#[cfg(not(test))]
 const HEAP_SIZE: usize = 1024 * 4;
+#[cfg(not(test))]
 static mut HEAP_MEMORY: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
 
+#[cfg(not(test))]
 #[entry]
 fn main() -> ! {
     unsafe {
@@ -32,57 +51,87 @@ fn main() -> ! {
     loop {}
 }
 
-/// Demonstrates tracing GC behavior with RAII root registration
-use core::cell::RefCell;
+/// GC-managed data structure
+pub struct MyData {
+    pub value: i32,
+    pub child: Option<Gc<dyn Trace>>,
+}
+
+impl Finalize for MyData {}
+
+impl Trace for MyData {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        if let Some(child) = self.child {
+            tracer.visit(child);
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
 
-fn run_example() {
+impl Drop for MyData {
+    fn drop(&mut self) {
+        say!("Dropping MyData with value = {}", self.value);
+    }
+}
+
+/// Demonstrates tracing GC behavior with RAII root registration; returns the
+/// stats of its two collections
+fn run_example() -> (CollectionStats, CollectionStats) {
     let heap = RefCell::new(Heap::new());
 
     // Allocate root1 and keep it rooted
     {
-        let mut heap_ref = heap.borrow_mut();
-        let root1 = heap_ref.allocate(MyData {
+        root!(heap, root1 = MyData {
             value: 100,
             child: None,
         });
-        let _guard1 = RootGuard::new(&mut *heap_ref, root1);
 
-        {
-            let mut heap_ref = heap.borrow_mut();
-            let root2 = heap_ref.allocate(MyData {
+        let both = {
+            root!(heap, root2 = MyData {
                 value: 200,
-                child: Some(root1),
+                child: Some(root1.unsize()),
             });
-            let _guard2 = RootGuard::new(&mut *heap_ref, root2);
 
-            unsafe {
-                let data1 = root1.deref().as_any().downcast_ref::<MyData>().unwrap();
-                let data2 = root2.deref().as_any().downcast_ref::<MyData>().unwrap();
+            say!("root1 value = {}", root1.value);
+            say!("root2 value = {}", root2.value);
 
-                hprintln!("root1 value = {}", data1.value);
-                hprintln!("root2 value = {}", data2.value);
-
-                if let Some(child) = &data2.child {
-                    let child_data = child.deref().as_any().downcast_ref::<MyData>().unwrap();
-                    hprintln!("root2 child value = {}", child_data.value);
-                }
+            if let Some(child) = &root2.child {
+                let child_data = child.downcast_ref::<MyData>().unwrap();
+                say!("root2 child value = {}", child_data.value);
             }
 
             // Collect GC with both roots alive
-            hprintln!("Collecting GC with root1 and root2");
-            let roots = heap.borrow().roots.borrow().clone();
-            heap.borrow_mut().collect_garbage(&roots);
-            // _guard2 drops here
-        }
+            say!("Collecting GC with root1 and root2");
+            let stats = heap.borrow_mut().collect();
+            say!("Freed {} objects ({} bytes)", stats.objects_freed, stats.bytes_freed);
+            // root2's guard drops here
+            stats
+        };
 
         // Now only root1 is rooted
-        hprintln!("Collecting GC with only root1");
-        let roots = heap.borrow().roots.borrow().clone();
-        heap.borrow_mut().collect_garbage(&roots);
+        say!("Collecting GC with only root1");
+        let stats = heap.borrow_mut().collect();
+        say!("Freed {} objects ({} bytes)", stats.objects_freed, stats.bytes_freed);
 
-        unsafe {
-            let data1 = root1.deref().as_any().downcast_ref::<MyData>().unwrap();
-            hprintln!("After GC, root1 value = {}", data1.value);
-        }
+        say!("After GC, root1 value = {}", root1.value);
+        (both, stats)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    //! `cargo test --bin embedded --target x86_64-unknown-linux-gnu` runs the
+    //! example on the host
+
+    use super::*;
+
+    #[test]
+    fn run_example_frees_root2_once_unrooted() {
+        let (both, one) = run_example();
+        assert_eq!((both.objects_freed, both.objects_surviving), (0, 2));
+        assert_eq!((one.objects_freed, one.objects_surviving), (1, 1));
     }
 }
@@ -0,0 +1,16 @@
+//! Misuse that must be rejected at compile time, each case in `tests/ui`
+//! with the expected compiler output next to it
+
+#[test]
+fn compile_fail() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
+
+/// `#[gc_trace]` on an incomplete `trace`, in `tests/ui-derive`
+#[cfg(feature = "derive")]
+#[test]
+fn compile_fail_derive() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui-derive/*.rs");
+}
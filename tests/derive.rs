@@ -0,0 +1,46 @@
+//! `#[derive(Trace, Finalize)]`, e.g.
+//! `cargo test --features derive --test derive --target x86_64-unknown-linux-gnu`
+#![cfg(feature = "derive")]
+
+use embedded::gc::{Finalize, Gc, GcCell, Heap, Trace};
+
+/// Every kind of field the derive recognizes
+#[derive(Trace, Finalize)]
+struct Node {
+    next: GcCell<Option<Gc<Node>>>,
+    parent: Option<Gc<Node>>,
+    children: Vec<Gc<Node>>,
+    #[trace(skip)]
+    id: u32,
+}
+
+fn node(id: u32, parent: Option<Gc<Node>>, children: Vec<Gc<Node>>) -> Node {
+    Node {
+        next: GcCell::new(None),
+        parent,
+        children,
+        id,
+    }
+}
+
+#[test]
+fn cycle_through_every_field_survives() {
+    let mut heap = Heap::with_threshold(1000);
+    let a = heap.allocate(node(1, None, Vec::new()));
+    let c = heap.allocate(node(3, None, vec![a]));
+    let b = heap.allocate(node(2, Some(c), Vec::new()));
+    // a -> b through `next`, b -> c through `parent`, c -> a through `children`
+    *a.next.borrow_mut(&mut heap, &a) = Some(b);
+    heap.register_root(a.as_non_null());
+    heap.allocate(node(4, None, Vec::new()));
+
+    let stats = heap.collect();
+    assert_eq!(stats.objects_freed, 1);
+    assert_eq!(stats.objects_surviving, 3);
+    let b = a.next.borrow().unwrap();
+    let c = b.parent.unwrap();
+    assert_eq!((b.id, c.id, c.children[0].id), (2, 3, 1));
+
+    heap.unregister_root(a.as_non_null());
+    assert_eq!(heap.collect().objects_freed, 3);
+}
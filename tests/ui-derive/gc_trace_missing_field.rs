@@ -0,0 +1,23 @@
+use core::any::Any;
+use embedded::gc::{gc_trace, Finalize, Gc, Trace, Tracer};
+
+struct Node {
+    left: Option<Gc<Node>>,
+    right: Option<Gc<Node>>,
+    value: u32,
+}
+
+impl Finalize for Node {}
+
+#[gc_trace(ignore(value))]
+impl Trace for Node {
+    fn trace(&self, tracer: &mut dyn Tracer) {
+        self.left.trace(tracer);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn main() {}
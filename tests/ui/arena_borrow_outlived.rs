@@ -0,0 +1,26 @@
+use core::any::Any;
+use embedded::gc::{Arena, Finalize, Trace, Tracer};
+
+struct Holder<'a>(&'a String);
+
+impl Drop for Holder<'_> {
+    fn drop(&mut self) {
+        println!("{}", self.0);
+    }
+}
+
+impl Finalize for Holder<'_> {}
+
+impl Trace for Holder<'_> {
+    fn trace(&self, _tracer: &mut dyn Tracer) {}
+
+    fn as_any(&self) -> &dyn Any {
+        &()
+    }
+}
+
+fn main() {
+    let mut arena = Arena::new();
+    let s = String::from("x");
+    arena.allocate(Holder(&s));
+}